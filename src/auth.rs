@@ -0,0 +1,555 @@
+use crate::errors::ClassificatorError;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::Arc;
+
+// Paths reachable without credentials
+const PUBLIC_PATHS: [&str; 1] = ["/health"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Maintains the label taxonomy
+    Admin,
+    // Labels part requests and moves them through the lifecycle
+    Classifier,
+    // Registers walk-in part requests
+    Counter,
+    // Delivers the notifications of the origin systems (whatsapp workflow, forms)
+    Ingestion,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Classifier => "classifier",
+            Role::Counter => "counter",
+            Role::Ingestion => "ingestion",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role.trim().to_lowercase().as_str() {
+            "admin" => Some(Role::Admin),
+            "classifier" => Some(Role::Classifier),
+            "counter" => Some(Role::Counter),
+            "ingestion" => Some(Role::Ingestion),
+            _ => None,
+        }
+    }
+}
+
+// Classifier behind a request, attached by the authentication middleware
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub subject: String,
+    pub roles: Vec<Role>,
+}
+
+impl Identity {
+    pub fn new(subject: &str, roles: Vec<Role>) -> Identity {
+        Identity {
+            subject: subject.to_string(),
+            roles,
+        }
+    }
+
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    pub fn require(&self, role: Role) -> Result<(), ClassificatorError> {
+        if !self.has_role(role) {
+            return Err(ClassificatorError::Forbidden(format!(
+                "'{}' doesnt have the {} role",
+                self.subject,
+                role.as_str()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl FromRequest for Identity {
+    type Error = ClassificatorError;
+    type Future = Ready<Result<Identity, ClassificatorError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let identity = req.extensions().get::<Identity>().cloned();
+
+        ready(identity.ok_or_else(|| {
+            ClassificatorError::Unauthorized(String::from("Request is not authenticated"))
+        }))
+    }
+}
+
+// Credentials found on the request headers
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credentials {
+    // X-Api-Key header
+    ApiKey(String),
+    // Authorization: Bearer header
+    Bearer(String),
+}
+
+impl Credentials {
+    fn from_request(req: &ServiceRequest) -> Option<Credentials> {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+
+        if let Some(key) = header("X-Api-Key") {
+            return Some(Credentials::ApiKey(key.to_string()));
+        }
+
+        let authorization = header("Authorization")?;
+        let token = authorization
+            .strip_prefix("Bearer ")
+            .or_else(|| authorization.strip_prefix("bearer "))?;
+
+        Some(Credentials::Bearer(token.trim().to_string()))
+    }
+}
+
+// Source of identities. Ok(None) means the credentials are not handled by this authenticator
+pub trait Authenticator: Send + Sync {
+    fn authenticate(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<Option<Identity>, ClassificatorError>;
+}
+
+// Static keys, each one bound to an identity
+pub struct ApiKeys {
+    keys: Vec<(String, Identity)>,
+}
+
+impl ApiKeys {
+    // Comma separated `key=subject:role|role` entries
+    pub fn parse(config: &str) -> Result<ApiKeys, ClassificatorError> {
+        let mut keys = vec![];
+
+        for entry in config.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let invalid = || {
+                ClassificatorError::Validation(String::from(
+                    "API keys are expected as key=subject:role|role",
+                ))
+            };
+
+            let (key, identity) = entry.split_once('=').ok_or_else(invalid)?;
+            let (subject, roles) = identity.split_once(':').ok_or_else(invalid)?;
+            if key.trim().is_empty() || subject.trim().is_empty() {
+                return Err(invalid());
+            }
+
+            let mut parsed = vec![];
+            for role in roles.split('|') {
+                match Role::parse(role) {
+                    Some(role) => parsed.push(role),
+                    None => {
+                        let err = format!("Unknown role '{}' for '{}'", role, subject);
+                        return Err(ClassificatorError::Validation(err));
+                    }
+                }
+            }
+
+            keys.push((
+                key.trim().to_string(),
+                Identity::new(subject.trim(), parsed),
+            ));
+        }
+
+        Ok(ApiKeys { keys })
+    }
+}
+
+// Compares every byte so the time taken doesn't reveal how much of a key matched
+fn same_key(expected: &str, received: &str) -> bool {
+    let (expected, received) = (expected.as_bytes(), received.as_bytes());
+    if expected.len() != received.len() {
+        return false;
+    }
+
+    expected
+        .iter()
+        .zip(received)
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+impl Authenticator for ApiKeys {
+    fn authenticate(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<Option<Identity>, ClassificatorError> {
+        let received = match credentials {
+            Credentials::ApiKey(key) => key,
+            _ => return Ok(None),
+        };
+
+        let mut found = None;
+        for (key, identity) in &self.keys {
+            if same_key(key, received) {
+                found = Some(identity.clone());
+            }
+        }
+
+        match found {
+            Some(identity) => Ok(Some(identity)),
+            None => Err(ClassificatorError::Unauthorized(String::from(
+                "API key is not valid",
+            ))),
+        }
+    }
+}
+
+// Claims read from the tokens, unknown roles are ignored
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub exp: u64,
+}
+
+// Bearer tokens signed with HS256 or RS256
+pub struct JwtAuthenticator {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl JwtAuthenticator {
+    pub fn hs256(secret: &[u8]) -> JwtAuthenticator {
+        JwtAuthenticator {
+            key: DecodingKey::from_secret(secret),
+            validation: Validation::new(Algorithm::HS256),
+        }
+    }
+
+    pub fn rs256(public_key_pem: &[u8]) -> Result<JwtAuthenticator, ClassificatorError> {
+        let key = DecodingKey::from_rsa_pem(public_key_pem).map_err(|err| {
+            ClassificatorError::Validation(format!("Invalid RS256 public key: {}", err))
+        })?;
+
+        Ok(JwtAuthenticator {
+            key,
+            validation: Validation::new(Algorithm::RS256),
+        })
+    }
+
+    pub fn with_issuer(mut self, issuer: &str) -> JwtAuthenticator {
+        self.validation.set_issuer(&[issuer]);
+        self
+    }
+
+    pub fn with_audience(mut self, audience: &str) -> JwtAuthenticator {
+        self.validation.set_audience(&[audience]);
+        self
+    }
+}
+
+impl Authenticator for JwtAuthenticator {
+    fn authenticate(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<Option<Identity>, ClassificatorError> {
+        let token = match credentials {
+            Credentials::Bearer(token) => token,
+            _ => return Ok(None),
+        };
+
+        let data = jsonwebtoken::decode::<Claims>(token, &self.key, &self.validation)
+            .map_err(|err| ClassificatorError::Unauthorized(format!("Invalid token: {}", err)))?;
+
+        let roles = data
+            .claims
+            .roles
+            .iter()
+            .filter_map(|r| Role::parse(r))
+            .collect();
+        Ok(Some(Identity::new(&data.claims.sub, roles)))
+    }
+}
+
+// Middleware resolving the identity of every request but the public paths
+#[derive(Clone)]
+pub struct Authentication {
+    authenticators: Arc<Vec<Box<dyn Authenticator>>>,
+    // Identity given to every request when authentication is disabled
+    anonymous: Option<Identity>,
+}
+
+impl Authentication {
+    pub fn new(authenticators: Vec<Box<dyn Authenticator>>) -> Authentication {
+        Authentication {
+            authenticators: Arc::new(authenticators),
+            anonymous: None,
+        }
+    }
+
+    // Every request acts as an anonymous user with every role, only meant for local development
+    pub fn disabled() -> Authentication {
+        Authentication {
+            authenticators: Arc::new(vec![]),
+            anonymous: Some(Identity::new(
+                "anonymous",
+                vec![
+                    Role::Admin,
+                    Role::Classifier,
+                    Role::Counter,
+                    Role::Ingestion,
+                ],
+            )),
+        }
+    }
+
+    // Authenticators configured through the AUTH_* environment variables
+    pub fn from_env() -> Result<Authentication, ClassificatorError> {
+        if env::var("AUTH_DISABLED").is_ok_and(|value| value == "true") {
+            warn!("Authentication is disabled, every request is trusted");
+            return Ok(Authentication::disabled());
+        }
+
+        let mut authenticators: Vec<Box<dyn Authenticator>> = vec![];
+
+        if let Ok(keys) = env::var("AUTH_API_KEYS") {
+            let keys = ApiKeys::parse(&keys)?;
+            info!("Loaded {} API keys", keys.keys.len());
+            authenticators.push(Box::new(keys));
+        }
+
+        if let Ok(algorithm) = env::var("AUTH_JWT_ALGORITHM") {
+            let mut jwt = match algorithm.as_str() {
+                "HS256" => match env::var("AUTH_JWT_SECRET") {
+                    Ok(secret) => JwtAuthenticator::hs256(secret.as_bytes()),
+                    Err(_) => return Err(missing_setting("AUTH_JWT_SECRET")),
+                },
+                "RS256" => {
+                    let path = env::var("AUTH_JWT_PUBLIC_KEY_FILE")
+                        .map_err(|_| missing_setting("AUTH_JWT_PUBLIC_KEY_FILE"))?;
+                    let pem = fs::read(&path).map_err(|err| {
+                        ClassificatorError::Validation(format!("Error reading {}: {}", path, err))
+                    })?;
+                    JwtAuthenticator::rs256(&pem)?
+                }
+                _ => {
+                    let err = format!("Unsupported JWT algorithm '{}'", algorithm);
+                    return Err(ClassificatorError::Validation(err));
+                }
+            };
+
+            if let Ok(issuer) = env::var("AUTH_JWT_ISSUER") {
+                jwt = jwt.with_issuer(&issuer);
+            }
+            if let Ok(audience) = env::var("AUTH_JWT_AUDIENCE") {
+                jwt = jwt.with_audience(&audience);
+            }
+
+            info!("Verifying {} tokens", algorithm);
+            authenticators.push(Box::new(jwt));
+        }
+
+        if authenticators.is_empty() {
+            return Err(ClassificatorError::Validation(String::from(
+                "No authentication configured, set AUTH_API_KEYS or AUTH_JWT_ALGORITHM",
+            )));
+        }
+
+        Ok(Authentication::new(authenticators))
+    }
+
+    fn identify(&self, req: &ServiceRequest) -> Result<Identity, ClassificatorError> {
+        if let Some(anonymous) = &self.anonymous {
+            return Ok(anonymous.clone());
+        }
+
+        let credentials = match Credentials::from_request(req) {
+            Some(credentials) => credentials,
+            None => {
+                return Err(ClassificatorError::Unauthorized(String::from(
+                    "Missing X-Api-Key or Authorization header",
+                )))
+            }
+        };
+
+        for authenticator in self.authenticators.iter() {
+            if let Some(identity) = authenticator.authenticate(&credentials)? {
+                debug!("Request authenticated as {}", identity.subject);
+                return Ok(identity);
+            }
+        }
+
+        Err(ClassificatorError::Unauthorized(String::from(
+            "Credentials type is not accepted",
+        )))
+    }
+}
+
+fn missing_setting(name: &str) -> ClassificatorError {
+    ClassificatorError::Validation(format!("{} has to be set", name))
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Authentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = AuthenticationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthenticationMiddleware {
+            service,
+            authentication: self.clone(),
+        }))
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: S,
+    authentication: Authentication,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if PUBLIC_PATHS.contains(&req.path()) {
+            return Box::pin(self.service.call(req));
+        }
+
+        match self.authentication.identify(&req) {
+            Ok(identity) => {
+                req.extensions_mut().insert(identity);
+                Box::pin(self.service.call(req))
+            }
+            Err(err) => {
+                warn!("Rejected {} {}: {}", req.method(), req.path(), err);
+                Box::pin(ready(Err(err.into())))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiKeys, Authentication, Authenticator, Claims, Identity, JwtAuthenticator, Role};
+    use crate::errors::ClassificatorError;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_and_read_body, init_service, try_call_service, TestRequest};
+    use actix_web::{get, App, HttpResponse};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    const SECRET: &[u8] = b"test-secret";
+
+    fn token(roles: &[&str], exp: u64) -> String {
+        let claims = Claims {
+            sub: String::from("ana"),
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+            exp,
+        };
+
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(SECRET),
+        )
+        .unwrap()
+    }
+
+    #[get("/whoami")]
+    async fn whoami(identity: Identity) -> HttpResponse {
+        HttpResponse::Ok().body(identity.subject)
+    }
+
+    #[test]
+    fn api_keys_parsed() {
+        let keys =
+            ApiKeys::parse("k1=ana:admin|classifier, k2=bot:classifier, k3=whatsapp:ingestion")
+                .unwrap();
+        let bot = keys.authenticate(&super::Credentials::ApiKey("k2".to_string()));
+        let workflow = keys.authenticate(&super::Credentials::ApiKey("k3".to_string()));
+
+        assert_eq!(
+            bot.unwrap(),
+            Some(Identity::new("bot", vec![Role::Classifier]))
+        );
+        assert_eq!(
+            workflow.unwrap(),
+            Some(Identity::new("whatsapp", vec![Role::Ingestion]))
+        );
+        assert!(ApiKeys::parse("k1=ana:owner").is_err());
+        assert!(ApiKeys::parse("k1").is_err());
+    }
+
+    // Passes if tokens are verified and expired or forged ones rejected
+    #[test]
+    fn jwt_verified() {
+        let jwt = JwtAuthenticator::hs256(SECRET);
+        let valid = super::Credentials::Bearer(token(&["classifier", "viewer"], 4102444800));
+        let expired = super::Credentials::Bearer(token(&["classifier"], 946684800));
+
+        let identity = jwt.authenticate(&valid).unwrap().unwrap();
+        assert_eq!(identity, Identity::new("ana", vec![Role::Classifier]));
+        assert!(identity.require(Role::Admin).is_err());
+
+        let res = jwt.authenticate(&expired);
+        assert!(matches!(res, Err(ClassificatorError::Unauthorized(_))));
+
+        let forged = JwtAuthenticator::hs256(b"other-secret").authenticate(&valid);
+        assert!(matches!(forged, Err(ClassificatorError::Unauthorized(_))));
+    }
+
+    #[actix_web::test]
+    async fn middleware_attaches_identity() {
+        let keys = ApiKeys::parse("k1=ana:classifier").unwrap();
+        let authentication = Authentication::new(vec![Box::new(keys)]);
+        let app = init_service(App::new().wrap(authentication).service(whoami)).await;
+
+        let req = TestRequest::get()
+            .uri("/whoami")
+            .insert_header(("X-Api-Key", "k1"))
+            .to_request();
+        let body = call_and_read_body(&app, req).await;
+        assert_eq!(body, "ana");
+
+        let req = TestRequest::get().uri("/whoami").to_request();
+        let res = try_call_service(&app, req).await;
+        let status = res.err().unwrap().as_response_error().status_code();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::get()
+            .uri("/whoami")
+            .insert_header(("X-Api-Key", "wrong"))
+            .to_request();
+        let res = try_call_service(&app, req).await;
+        let status = res.err().unwrap().as_response_error().status_code();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
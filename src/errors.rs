@@ -0,0 +1,143 @@
+use crate::vin::VinError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use log::error;
+use redis::RedisError;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClassificatorError {
+    // Request, label or sub-resource doesn't exist
+    NotFound(String),
+    // Operation is not valid on the current state (label already assigned...)
+    Conflict(String),
+    // Invalid input received from the client
+    Validation(String),
+    // Missing or invalid credentials
+    Unauthorized(String),
+    // Authenticated without the role the operation needs
+    Forbidden(String),
+    // Redis failed or returned data that couldn't be parsed
+    Storage(String),
+    // Origin system of a request (whatsapp tracker...) couldn't be queried
+    SourceUnavailable(String),
+    Internal(String),
+}
+
+// Body returned to clients on every error
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: &'a str,
+}
+
+impl ClassificatorError {
+    // Stable machine-readable code sent in the response body
+    pub fn code(&self) -> &'static str {
+        match self {
+            ClassificatorError::NotFound(_) => "NOT_FOUND",
+            ClassificatorError::Conflict(_) => "CONFLICT",
+            ClassificatorError::Validation(_) => "VALIDATION_ERROR",
+            ClassificatorError::Unauthorized(_) => "UNAUTHORIZED",
+            ClassificatorError::Forbidden(_) => "FORBIDDEN",
+            ClassificatorError::Storage(_) => "STORAGE_ERROR",
+            ClassificatorError::SourceUnavailable(_) => "SOURCE_UNAVAILABLE",
+            ClassificatorError::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ClassificatorError::NotFound(message)
+            | ClassificatorError::Conflict(message)
+            | ClassificatorError::Validation(message)
+            | ClassificatorError::Unauthorized(message)
+            | ClassificatorError::Forbidden(message)
+            | ClassificatorError::Storage(message)
+            | ClassificatorError::SourceUnavailable(message)
+            | ClassificatorError::Internal(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for ClassificatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for ClassificatorError {}
+
+impl ResponseError for ClassificatorError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ClassificatorError::NotFound(_) => StatusCode::NOT_FOUND,
+            ClassificatorError::Conflict(_) => StatusCode::CONFLICT,
+            ClassificatorError::Validation(_) => StatusCode::BAD_REQUEST,
+            ClassificatorError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ClassificatorError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ClassificatorError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ClassificatorError::SourceUnavailable(_) => StatusCode::BAD_GATEWAY,
+            ClassificatorError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.message(),
+        };
+
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
+
+impl From<RedisError> for ClassificatorError {
+    fn from(err: RedisError) -> Self {
+        error!("Redis error: {}", err);
+        ClassificatorError::Storage(err.to_string())
+    }
+}
+
+impl From<VinError> for ClassificatorError {
+    fn from(err: VinError) -> Self {
+        ClassificatorError::Validation(err.to_string())
+    }
+}
+
+impl From<actix_web::error::BlockingError> for ClassificatorError {
+    fn from(err: actix_web::error::BlockingError) -> Self {
+        error!("Blocking task failed: {}", err);
+        ClassificatorError::Internal(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClassificatorError;
+    use actix_web::body::to_bytes;
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+
+    #[test]
+    fn maps_status_codes() {
+        let not_found = ClassificatorError::NotFound("missing".to_string());
+        let validation = ClassificatorError::Validation("invalid".to_string());
+        let conflict = ClassificatorError::Conflict("twice".to_string());
+
+        assert_eq!(not_found.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(validation.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(conflict.status_code(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn body_contains_code_and_message() {
+        let err = ClassificatorError::NotFound("Label '3' doesnt exist".to_string());
+        let body = to_bytes(err.error_response().into_body()).await.unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["code"], "NOT_FOUND");
+        assert_eq!(json["message"], "Label '3' doesnt exist");
+    }
+}
@@ -1,226 +1,650 @@
-use crate::redis::classification::{
-    append_label, complete_request, get_label_childs, get_pending_classification_requests,
-    get_request_labels, remove_label, year_selection,
-};
-use crate::redis::part_register::{create_part_request, get_request_by_id};
 use fizzy_commons::shared_structs::{MessageLog, ModifiedReference, StandardResponse};
 use log::{debug, error};
 
-use crate::helpers::{process_new_request, remove_label_tree, retrieve_label_tree};
-use crate::structs::classification::Label;
+use crate::errors::ClassificatorError;
+use crate::helpers::{
+    build_label_tree, create_request, diff_labels, format_labels, parse_labels,
+    resolve_assigned_labels, validate_labels, verify_label_id, verify_label_name,
+};
+use crate::request_structs::{
+    FormSubmission, LabelFormat, NewPartRequest, RequestListQuery, TextSearchQuery,
+};
+use crate::storage::{
+    LabelStore, RequestAggregate, RequestFilter, RequestInclude, RequestStore,
+    DEFAULT_CLAIM_TTL_MS, DEFAULT_PAGE_LIMIT, MAX_CLAIM_TTL_MS, MAX_PAGE_LIMIT, MIN_CLAIM_TTL_MS,
+};
+use crate::structs::classification::{Label, LabelDiff, LabelNode};
 use crate::structs::part_request::{
-    PartRequest, RequestDetailsBuilder, RequestorBuilder, VehicleDataBuilder,
+    Claim, ClaimedRequest, PartRequest, RequestDetails, RequestEvent, RequestMatches, RequestPage,
+    RequestStatus, Requestor, StatusTransition, VehicleData, YearSource,
 };
-use crate::structs::WhatsappSource;
+use crate::structs::{OriginSystem, SourceRegistry};
+use crate::vin;
+
+// Origin of the requests registered at the counter through POST /request
+const COUNTER_ORIGIN: &str = "COUNTER";
 
-pub fn get_all_labels() -> Result<Vec<Label>, String> {
-    crate::redis::classification::get_all_labels()
+pub fn get_all_labels<S: LabelStore>(store: &S) -> Result<Vec<Label>, ClassificatorError> {
+    store.get_all_labels()
 }
 
-pub fn get_labels(label_id: String) -> Result<Vec<Label>, String> {
+pub fn get_label_tree<S: LabelStore>(
+    store: &S,
+    root: Option<String>,
+    depth: Option<usize>,
+) -> Result<Vec<LabelNode>, ClassificatorError> {
+    if depth == Some(0) {
+        return Err(ClassificatorError::Validation(String::from(
+            "Depth has to be greater than 0",
+        )));
+    }
+
+    let labels = store.get_all_labels()?;
+    let root = root.unwrap_or_else(|| String::from("0"));
+
+    build_label_tree(&labels, &root, depth)
+}
+
+pub fn get_labels<S: LabelStore>(
+    store: &S,
+    label_id: String,
+) -> Result<Vec<Label>, ClassificatorError> {
     // Don't verify if looking for parent label as label with id 0 doesn't exist
     if label_id != "0" {
         // Verify is label with specified code exists
-        let label_exists = Label::exists(&label_id);
+        let label_exists = store.label_exists(&label_id)?;
         if !label_exists {
             let err = format!("Label with id '{}' doesnt exist", &label_id);
-            return Err(err);
+            return Err(ClassificatorError::NotFound(err));
         }
     }
 
     // Get label which parent corresponds to label_id
-    let res = get_label_childs(&label_id);
-    if res.is_err() {
-        let err = format!("Error obtaining labels: {}", res.unwrap_err());
-        error!("{}", err);
-        return Err(err);
+    store
+        .get_label_childs(&label_id)
+        .inspect_err(|err| error!("Error obtaining labels: {}", err))
+}
+
+pub fn create_label<S: LabelStore>(
+    store: &S,
+    id: Option<String>,
+    name: String,
+    parent: Option<String>,
+) -> Result<Label, ClassificatorError> {
+    if let Some(id) = &id {
+        verify_label_id(id)?;
     }
+    let name = verify_label_name(&name)?;
+    // Labels without parent are base labels
+    let parent = parent.unwrap_or_else(|| String::from("0"));
 
-    Ok(res.unwrap())
+    store
+        .create_label(id.as_deref(), name, &parent)
+        .inspect_err(|err| error!("Error creating label: {}", err))
 }
 
-pub fn remove_request_labels(
-    request_id: String,
-    label_id: String,
-) -> Result<StandardResponse, StandardResponse> {
+pub fn update_label<S: LabelStore>(
+    store: &S,
+    id: String,
+    name: Option<String>,
+    parent: Option<String>,
+) -> Result<Label, ClassificatorError> {
+    if name.is_none() && parent.is_none() {
+        return Err(ClassificatorError::Validation(String::from(
+            "Either name or parent has to be updated",
+        )));
+    }
+
+    let name = match &name {
+        Some(name) => Some(verify_label_name(name)?),
+        None => None,
+    };
+
+    store
+        .update_label(&id, name, parent.as_deref())
+        .inspect_err(|err| error!("Error updating label: {}", err))
+}
+
+pub fn delete_label<S: LabelStore>(
+    store: &S,
+    id: String,
+    cascade: bool,
+) -> Result<StandardResponse, ClassificatorError> {
     let mut response = StandardResponse {
         references: vec![],
         errors: None,
     };
 
-    // Verify if part request exists
-    let request_exists = PartRequest::exists(&request_id);
+    let deleted = store.delete_label(&id, cascade)?;
 
-    if !request_exists {
-        let err = format!("Part request '{}' doesnt exist", &request_id);
-        response.errors = Some(vec![err]);
-        return Err(response);
+    for label_id in deleted {
+        response.references.push(ModifiedReference {
+            system: "REDIS".to_string(),
+            reference: label_id,
+        });
     }
 
-    // Get label to check if child labels are added and remove them
-    let label = Label::get(&label_id);
+    Ok(response)
+}
+
+pub fn export_labels<S: LabelStore>(
+    store: &S,
+    format: LabelFormat,
+) -> Result<String, ClassificatorError> {
+    let labels = store.get_all_labels()?;
 
-    // Get all labels added to request
-    let assigned_labels: Result<Vec<Label>, String> = get_request_labels(&request_id);
+    format_labels(&labels, format)
+}
+
+// Replaces the taxonomy with the one received, dry runs only report the changes
+pub fn import_labels<S: LabelStore>(
+    store: &S,
+    body: &[u8],
+    format: LabelFormat,
+    dry_run: bool,
+) -> Result<LabelDiff, ClassificatorError> {
+    let labels = parse_labels(body, format)?;
+    validate_labels(&labels)?;
 
-    // Delete child labels recursively
+    let current = store.get_all_labels()?;
+    let mut diff = diff_labels(&current, &labels);
 
-    // Remove label from request
-    debug!("assigned labels: {:?}", assigned_labels.as_ref().unwrap());
-    let res = remove_label_tree(&request_id, &label_id, &mut assigned_labels.unwrap());
+    if !dry_run {
+        store
+            .replace_labels(&labels)
+            .inspect_err(|err| error!("Error importing labels: {}", err))?;
 
-    if res.is_err() {
-        response.errors = Some(vec![res.unwrap_err()]);
-        return Err(response);
+        diff.applied = true;
     }
 
-    Ok(response)
+    Ok(diff)
 }
 
-pub fn get_request(request_id: &str) -> Result<PartRequest, String> {
-    let part_request_res = PartRequest::get_request(request_id);
+pub fn remove_request_labels<S: RequestStore>(
+    store: &S,
+    request_id: String,
+    label_id: String,
+    actor: &str,
+) -> Result<Vec<Label>, ClassificatorError> {
+    verify_claim(store, &request_id, actor)?;
+
+    // Remove label and its child labels from request, returns the labels left
+    let labels = store
+        .remove_label_tree(&request_id, &label_id, actor)
+        .inspect_err(|err| error!("Error removing label: {}", err))?;
 
-    if part_request_res.is_err() {
-        return Err(part_request_res.unwrap_err());
-    }
+    debug!("assigned labels: {:?}", labels);
+    Ok(labels)
+}
 
-    let mut part_request = part_request_res.unwrap();
+pub fn get_request<S: RequestStore + LabelStore>(
+    store: &S,
+    request_id: &str,
+    include: Option<&str>,
+) -> Result<PartRequest, ClassificatorError> {
+    // Every sub-resource is loaded unless told otherwise
+    let include = match include {
+        Some(include) => RequestInclude::parse(include)?,
+        None => RequestInclude::all(),
+    };
 
-    let vehicle_data = part_request.get_vehicle_data();
+    let aggregate = store
+        .load_request(request_id, &include)
+        .inspect_err(|err| error!("Error obtaining part request: {}", err))?;
+    let mut part_request = aggregate.request;
 
-    if vehicle_data.is_err() {
-        return Err(vehicle_data.unwrap_err());
+    if include.labels {
+        part_request.labels = Some(resolve_assigned_labels(store, aggregate.labels)?);
     }
 
     Ok(part_request)
 }
 
-pub fn select_year(request_id: String, year: &str) -> Result<(), String> {
-    let res = year_selection(&request_id, year);
+pub fn select_year<S: RequestStore>(
+    store: &S,
+    request_id: String,
+    year: &str,
+    actor: &str,
+) -> Result<(), ClassificatorError> {
+    if year.len() != 4 || year.parse::<u16>().is_err() {
+        let err = format!("Year '{}' is not a valid year", year);
+        return Err(ClassificatorError::Validation(err));
+    }
+
+    verify_request_exists(store, &request_id)?;
+    verify_claim(store, &request_id, actor)?;
 
-    if res.is_err() {
-        error!("{}", res.as_ref().unwrap_err());
-        return Err(res.as_ref().unwrap_err().to_string());
+    store
+        .year_selection(&request_id, year, actor)
+        .inspect_err(|err| error!("Error selecting year: {}", err))
+}
+
+pub fn classification_completed<S: RequestStore + LabelStore>(
+    store: &S,
+    request_id: String,
+    actor: &str,
+) -> Result<StatusTransition, ClassificatorError> {
+    transition_request(store, request_id, RequestStatus::Classified.as_str(), actor)
+}
+
+pub fn transition_request<S: RequestStore + LabelStore>(
+    store: &S,
+    request_id: String,
+    status: &str,
+    actor: &str,
+) -> Result<StatusTransition, ClassificatorError> {
+    let to = parse_status(status)?;
+
+    let include = RequestInclude {
+        vehicle: true,
+        labels: true,
+        ..RequestInclude::default()
+    };
+    let aggregate = store.load_request(&request_id, &include)?;
+    let from = aggregate.request.classified;
+    verify_claim(store, &request_id, actor)?;
+
+    if !from.can_transition_to(to) {
+        let allowed: Vec<&str> = from.next_statuses().iter().map(|s| s.as_str()).collect();
+        let allowed = match allowed.is_empty() {
+            true => String::from("none"),
+            false => allowed.join(", "),
+        };
+        return Err(ClassificatorError::Conflict(format!(
+            "Part request '{}' cant move from {} to {}, allowed: {}",
+            request_id, from, to, allowed
+        )));
     }
 
-    Ok(())
+    if to == RequestStatus::Classified {
+        verify_classification(store, &aggregate)?;
+    }
+
+    if let Err(err) = store.set_request_status(&request_id, from, to, actor) {
+        error!("{}", err);
+        return Err(err);
+    }
+
+    // The request leaves the queue, nobody has to keep working on it
+    if !matches!(to, RequestStatus::Pending | RequestStatus::InReview) {
+        let claim = store.get_claim(&request_id)?;
+        if claim.is_some_and(|claim| claim.owner == actor) {
+            store.release_claim(&request_id, actor)?;
+        }
+    }
+
+    Ok(StatusTransition {
+        request_id,
+        from,
+        to,
+    })
 }
-pub fn classification_completed(request_id: String) -> Result<(), String> {
-    let res = complete_request(&request_id);
 
-    if res.is_err() {
-        error!("{}", res.as_ref().unwrap_err());
-        return Err(res.as_ref().unwrap_err().to_string());
+// A request is only classified with a leaf label of the taxonomy and the vehicle year
+fn verify_classification<S: LabelStore>(
+    store: &S,
+    aggregate: &RequestAggregate,
+) -> Result<(), ClassificatorError> {
+    let mut missing = vec![];
+
+    let label_ids: Vec<String> = aggregate.labels.iter().map(|l| l.id.clone()).collect();
+    if store.leaf_labels(&label_ids)?.is_empty() {
+        missing.push("a leaf label");
+    }
+
+    let vehicle = aggregate.request.vehicle.as_ref();
+    // Candidates left by the VIN decoder don't count until one is selected
+    let year = vehicle.and_then(|v| v.year.as_deref()).unwrap_or("");
+    if year.is_empty() || year.contains(',') {
+        missing.push("a selected year");
+    }
+
+    if !missing.is_empty() {
+        return Err(ClassificatorError::Conflict(format!(
+            "Part request '{}' cant be classified without {}",
+            aggregate.request.id,
+            missing.join(" and ")
+        )));
     }
 
     Ok(())
 }
 
-pub fn update_request_labels(
+pub fn update_request_labels<S: RequestStore>(
+    store: &S,
     request_id: String,
     label_id: String,
-) -> Result<StandardResponse, StandardResponse> {
-    let mut response = StandardResponse {
-        references: vec![],
-        errors: None,
-    };
+    actor: &str,
+) -> Result<Vec<Label>, ClassificatorError> {
     use std::time::Instant;
-    let mut now = Instant::now();
+    let now = Instant::now();
 
-    // Verify is label with specified code exists
-    let label_exists = Label::exists(&label_id);
+    verify_claim(store, &request_id, actor)?;
 
-    let mut elapsed = now.elapsed();
-    debug!("Label::exists Elapsed: {:.2?}", elapsed);
+    // Append label and its ancestors to request, fails if it was already added
+    let res = store.assign_label_tree(&request_id, &label_id, actor);
 
-    if !label_exists {
-        let err = format!("Label '{}' doesnt exist", &label_id);
-        response.errors = Some(vec![err]);
-        return Err(response);
-    }
+    let elapsed = now.elapsed();
+    debug!("Append label tree Elapsed: {:.2?}", elapsed);
 
-    now = Instant::now();
+    res.inspect_err(|err| error!("Error appending label: {}", err))
+}
 
-    // Verify if part request exists
-    let request_exists = PartRequest::exists(&request_id);
+pub fn get_request_history<S: RequestStore>(
+    store: &S,
+    request_id: &str,
+) -> Result<Vec<RequestEvent>, ClassificatorError> {
+    store
+        .get_request_history(request_id)
+        .inspect_err(|err| error!("Error obtaining request history: {}", err))
+}
 
-    elapsed = now.elapsed();
-    debug!("PartRequest::exists Elapsed: {:.2?}", elapsed);
+pub fn claim_next_request<S: RequestStore>(
+    store: &S,
+    actor: &str,
+    ttl_ms: Option<u64>,
+) -> Result<Option<ClaimedRequest>, ClassificatorError> {
+    let ttl_ms = claim_ttl(ttl_ms)?;
+    let res = store.claim_next_request(actor, ttl_ms);
 
-    if !request_exists {
-        let err = format!("Part request '{}' doesnt exist", &request_id);
-        response.errors = Some(vec![err]);
-        return Err(response);
+    match &res {
+        Ok(Some(claimed)) => debug!("{} claimed {}", actor, claimed.request.id),
+        Ok(None) => debug!("No pending requests left for {}", actor),
+        Err(err) => error!("Error claiming request: {}", err),
     }
 
-    // Get label to check parent
-    let label = Label::get(&label_id).expect("Failed to get label:");
-    debug!("label: {label:?}");
-    now = Instant::now();
-    let mut label_list: Vec<Label> = vec![label.clone()];
-    if label.parent != "0" {
-        let list = retrieve_label_tree(&label.parent, &mut label_list);
-        debug!("Label list: {:?}", label_list)
+    res
+}
+
+pub fn renew_claim<S: RequestStore>(
+    store: &S,
+    request_id: String,
+    actor: &str,
+    ttl_ms: Option<u64>,
+) -> Result<Claim, ClassificatorError> {
+    let ttl_ms = claim_ttl(ttl_ms)?;
+    store.renew_claim(&request_id, actor, ttl_ms)
+}
+
+pub fn release_claim<S: RequestStore>(
+    store: &S,
+    request_id: String,
+    actor: &str,
+) -> Result<(), ClassificatorError> {
+    store.release_claim(&request_id, actor)
+}
+
+fn claim_ttl(ttl_ms: Option<u64>) -> Result<u64, ClassificatorError> {
+    let ttl_ms = ttl_ms.unwrap_or(DEFAULT_CLAIM_TTL_MS);
+
+    if !(MIN_CLAIM_TTL_MS..=MAX_CLAIM_TTL_MS).contains(&ttl_ms) {
+        return Err(ClassificatorError::Validation(format!(
+            "Claim ttl has to be between {} and {} ms",
+            MIN_CLAIM_TTL_MS, MAX_CLAIM_TTL_MS
+        )));
     }
 
-    elapsed = now.elapsed();
-    debug!("Label tree Elapsed: {:.2?}", elapsed);
+    Ok(ttl_ms)
+}
 
-    now = Instant::now();
-    // Append label to request
-    for iter_label in label_list {
-        let res = append_label(&request_id, &iter_label.id);
+// Requests claimed by another classifier can't be changed until the claim ends
+fn verify_claim<S: RequestStore>(
+    store: &S,
+    request_id: &str,
+    actor: &str,
+) -> Result<(), ClassificatorError> {
+    match store.get_claim(request_id)? {
+        Some(claim) if claim.owner != actor => Err(ClassificatorError::Conflict(format!(
+            "Part request '{}' is claimed by {} until {}",
+            request_id, claim.owner, claim.expires_at
+        ))),
+        _ => Ok(()),
+    }
+}
 
-        if res.is_err() {
-            response.errors = Some(vec![res.unwrap_err()]);
-            return Err(response);
+fn parse_status(status: &str) -> Result<RequestStatus, ClassificatorError> {
+    match RequestStatus::parse(status) {
+        Some(status) => Ok(status),
+        None => {
+            let valid: Vec<&str> = RequestStatus::ALL.iter().map(|s| s.as_str()).collect();
+            Err(ClassificatorError::Validation(format!(
+                "Status '{}' doesnt exist, expected one of: {}",
+                status,
+                valid.join(", ")
+            )))
         }
     }
+}
 
-    elapsed = now.elapsed();
-    debug!("Append label Elapsed: {:.2?}", elapsed);
+// Trimmed value, None if it's missing or empty
+fn provided(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
 
-    Ok(response)
+fn page_limit(limit: Option<usize>) -> Result<usize, ClassificatorError> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+
+    if limit == 0 || limit > MAX_PAGE_LIMIT {
+        return Err(ClassificatorError::Validation(format!(
+            "Limit must be between 1 and {}",
+            MAX_PAGE_LIMIT
+        )));
+    }
+
+    Ok(limit)
+}
+
+// Requests which description, VIN, make or model contain every word of q
+pub fn search_request_text<S: RequestStore>(
+    store: &S,
+    query: TextSearchQuery,
+) -> Result<RequestMatches, ClassificatorError> {
+    let text = query.q.trim();
+    if !text.chars().any(char::is_alphanumeric) {
+        return Err(ClassificatorError::Validation(String::from(
+            "Search text must contain at least one word",
+        )));
+    }
+
+    let limit = page_limit(query.limit)?;
+    store.search_request_text(text, query.offset.unwrap_or(0), limit)
 }
 
-pub fn get_pending_requests() -> Result<Vec<PartRequest>, String> {
-    let res = get_pending_classification_requests();
+// Requests matching the query, PENDING ones unless another status (or ANY) is given
+pub fn search_requests<S: RequestStore>(
+    store: &S,
+    query: RequestListQuery,
+) -> Result<RequestPage, ClassificatorError> {
+    let status = match query.status.as_deref().map(str::trim) {
+        None | Some("") => Some(RequestStatus::Pending),
+        Some(status) if status.eq_ignore_ascii_case("ANY") => None,
+        Some(status) => Some(parse_status(status)?),
+    };
+
+    let limit = page_limit(query.limit)?;
 
-    if res.is_err() {
-        return Err(format!("{}", res.unwrap_err()));
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(ClassificatorError::Validation(String::from(
+                "Date range start is after its end",
+            )));
+        }
     }
-    Ok(res.unwrap())
+
+    // Empty values are treated as missing filters
+    let filter = RequestFilter {
+        status,
+        origin: provided(query.origin),
+        make: provided(query.make),
+        model: provided(query.model),
+        year: provided(query.year),
+        requestor: provided(query.requestor),
+        from: query.from,
+        to: query.to,
+        sort: query.sort,
+        offset: query.offset.unwrap_or(0),
+        limit,
+    };
+
+    store.search_requests(&filter)
 }
 
-pub fn new_request_received(
-    notification: MessageLog,
-) -> Result<StandardResponse, StandardResponse> {
-    let mut response = StandardResponse {
-        references: vec![],
-        errors: None,
+// Walk-in requests registered at the counter, the vehicle is identified by its VIN or by its
+// make, model and year
+pub fn create_part_request<S: RequestStore>(
+    store: &S,
+    new_request: NewPartRequest,
+) -> Result<PartRequest, ClassificatorError> {
+    let requestor_id = provided(Some(new_request.requestor_id)).ok_or_else(|| {
+        ClassificatorError::Validation(String::from("Requestor id can't be empty"))
+    })?;
+
+    let year = provided(new_request.year);
+    if let Some(year) = &year {
+        if year.len() != 4 || year.parse::<u16>().is_err() {
+            let err = format!("Year '{}' is not a valid year", year);
+            return Err(ClassificatorError::Validation(err));
+        }
+    }
+
+    let mut vehicle = VehicleData {
+        make: provided(new_request.make),
+        model: provided(new_request.model),
+        year_source: year.as_ref().map(|_| YearSource::Manual),
+        year,
+        ..VehicleData::default()
     };
 
-    match notification.origin_system.parse::<u16>().unwrap() {
-        3 => {
-            // User requested part
-            let part_request = process_new_request(&notification);
+    match provided(new_request.vin) {
+        Some(vin) => {
+            let information = vin::decode(&vin)?;
 
-            if part_request.is_err() {
-                response.errors = Some(vec!["Error creating part request".to_string()]);
-                return Err(response);
+            // A known year is kept, otherwise the classifier picks one of the candidates unless
+            // a single one is left
+            if vehicle.year.is_none() {
+                vehicle.year = Some(information.years.join(","));
+                vehicle.year_source = information.year.as_ref().map(|_| YearSource::Inferred);
             }
-
-            response.references.push(ModifiedReference {
-                system: "REDIS".to_string(),
-                reference: part_request.unwrap().id,
-            });
-            Ok(response)
+            vehicle.check_vin(&information);
+            vehicle.vin = Some(information.vin);
         }
-        _ => {
-            error!("Unexpected system");
-            Err(response)
+        None if vehicle.make.is_some() && vehicle.model.is_some() && vehicle.year.is_some() => {}
+        None => {
+            return Err(ClassificatorError::Validation(String::from(
+                "Either the VIN or the make, model and year of the vehicle are required",
+            )));
         }
     }
+
+    // Requests without a ticket reference are referenced by their own id
+    let mut part_request = PartRequest::pending(COUNTER_ORIGIN, "");
+    part_request.origin_reference =
+        provided(new_request.reference).unwrap_or_else(|| part_request.id.clone());
+
+    part_request.vehicle = Some(vehicle);
+    part_request.details = Some(RequestDetails {
+        description: provided(new_request.description),
+        attached_files: provided(new_request.attached_files),
+    });
+    part_request.requestor = Some(Requestor {
+        user_id: requestor_id,
+    });
+
+    let res = store.create_part_request(&part_request);
+
+    if let Err(err) = res {
+        error!("Error creating part request: {}", err);
+        return Err(err);
+    }
+
+    Ok(part_request)
+}
+
+pub fn new_request_received<S: RequestStore>(
+    store: &S,
+    registry: &SourceRegistry,
+    notification: MessageLog,
+) -> Result<StandardResponse, ClassificatorError> {
+    let system = origin_system(&notification.origin_system)?;
+
+    let source = registry.source(system, None).ok_or_else(|| {
+        ClassificatorError::Validation(format!(
+            "Origin system '{}' sends its requests to /incoming/form",
+            notification.origin_system
+        ))
+    })?;
+
+    let part_request = create_request(
+        store,
+        &source,
+        system.origin(),
+        &notification.register_id,
+        &notification.phone_number,
+    )?;
+
+    Ok(created_response(part_request))
+}
+
+// Web form and email requests, their data is read from the submission itself
+pub fn form_submission_received<S: RequestStore>(
+    store: &S,
+    registry: &SourceRegistry,
+    submission: FormSubmission,
+) -> Result<StandardResponse, ClassificatorError> {
+    let system = origin_system(&submission.origin_system)?;
+
+    if submission.reference.trim().is_empty() {
+        return Err(ClassificatorError::Validation(String::from(
+            "Reference can't be empty",
+        )));
+    }
+
+    let reference = submission.reference.clone();
+    let phone_number = submission.phone_number.clone();
+
+    let source = registry.source(system, Some(submission)).ok_or_else(|| {
+        ClassificatorError::Validation(format!(
+            "Origin system '{}' sends its requests to /incoming",
+            system.origin()
+        ))
+    })?;
+
+    let part_request = create_request(store, &source, system.origin(), &reference, &phone_number)?;
+
+    Ok(created_response(part_request))
+}
+
+fn origin_system(id: &str) -> Result<OriginSystem, ClassificatorError> {
+    let system = id.parse::<u16>().ok().and_then(OriginSystem::from_id);
+
+    system.ok_or_else(|| {
+        error!("Unexpected system");
+        ClassificatorError::Validation(format!("Unexpected origin system '{}'", id))
+    })
+}
+
+fn created_response(part_request: PartRequest) -> StandardResponse {
+    StandardResponse {
+        references: vec![ModifiedReference {
+            system: "REDIS".to_string(),
+            reference: part_request.id,
+        }],
+        errors: None,
+    }
+}
+
+fn verify_request_exists<S: RequestStore>(
+    store: &S,
+    request_id: &str,
+) -> Result<(), ClassificatorError> {
+    if !store.request_exists(request_id)? {
+        let err = format!("Part request '{}' doesnt exist", request_id);
+        return Err(ClassificatorError::NotFound(err));
+    }
+
+    Ok(())
 }
 
 // pub fn outgoing_messages() -> Result<StandardResponse, StandardResponse>{
@@ -232,4 +656,802 @@ pub fn new_request_received(
 // }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::{
+        claim_next_request, classification_completed, create_label, create_part_request,
+        delete_label, export_labels, form_submission_received, get_label_tree, get_labels,
+        get_request, get_request_history, import_labels, release_claim, remove_request_labels,
+        renew_claim, search_request_text, search_requests, select_year, transition_request,
+        update_label, update_request_labels,
+    };
+    use crate::errors::ClassificatorError;
+    use crate::request_structs::{
+        LabelFormat, NewPartRequest, RequestListQuery, SortOrder, TextSearchQuery,
+    };
+    use crate::storage::memory::InMemoryStore;
+    use crate::storage::{fixtures, LabelStore, RequestStore};
+    use crate::structs::classification::Label;
+    use crate::structs::fixtures::{registry, submission};
+    use crate::structs::part_request::{
+        PartRequest, RequestAction, RequestDetails, RequestStatus, Requestor, VehicleData,
+        YearSource,
+    };
+
+    #[test]
+    fn missing_label_not_found() {
+        let store = fixtures::store();
+        let res = update_request_labels(&store, "req-1".to_string(), "99".to_string(), "tester");
+
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+    }
+
+    #[test]
+    fn missing_request_not_found() {
+        let store = fixtures::store();
+        let res = update_request_labels(&store, "req-404".to_string(), "3".to_string(), "tester");
+
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+    }
+
+    #[test]
+    fn duplicated_label_conflict() {
+        let store = fixtures::store();
+        update_request_labels(&store, "req-1".to_string(), "3".to_string(), "tester").unwrap();
+        let res = update_request_labels(&store, "req-1".to_string(), "3".to_string(), "tester");
+
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+    }
+
+    #[test]
+    fn ancestors_assigned() {
+        let store = fixtures::store();
+        let labels =
+            update_request_labels(&store, "req-1".to_string(), "3".to_string(), "tester").unwrap();
+
+        let mut ids: Vec<String> = labels.into_iter().map(|label| label.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["1", "2", "3"]);
+    }
+
+    // Passes if nothing is assigned when a label of the chain is missing
+    #[test]
+    fn broken_chain_not_assigned() {
+        let store = fixtures::store();
+        store.insert_label(Label::new("6", "Hoses", "99"));
+
+        let res = update_request_labels(&store, "req-1".to_string(), "6".to_string(), "tester");
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+        let request = get_request(&store, "req-1", Some("labels")).unwrap();
+        assert!(request.labels.unwrap().is_empty());
+    }
+
+    #[test]
+    fn descendants_removed() {
+        let store = fixtures::store();
+        update_request_labels(&store, "req-1".to_string(), "3".to_string(), "tester").unwrap();
+        update_request_labels(&store, "req-1".to_string(), "4".to_string(), "tester").unwrap();
+
+        let labels =
+            remove_request_labels(&store, "req-1".to_string(), "2".to_string(), "tester").unwrap();
+
+        let mut ids: Vec<String> = labels.into_iter().map(|label| label.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["1", "4"]);
+    }
+
+    #[test]
+    fn unassigned_label_conflict() {
+        let store = fixtures::store();
+        let res = remove_request_labels(&store, "req-1".to_string(), "5".to_string(), "tester");
+
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+    }
+
+    #[test]
+    fn invalid_year_validation() {
+        let store = fixtures::store();
+        let res = select_year(&store, "req-1".to_string(), "20x3", "tester");
+
+        assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+    }
+
+    // Passes if the store itself refuses the year of an unknown request
+    #[test]
+    fn year_of_missing_request() {
+        let store = fixtures::store();
+        let res = store.year_selection("req-404", "2003", "tester");
+
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+    }
+
+    #[test]
+    fn child_labels_of_missing_label() {
+        let store = fixtures::store();
+        let res = get_labels(&store, "99".to_string());
+
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+    }
+
+    #[test]
+    fn label_created_with_next_id() {
+        let store = fixtures::store();
+        let parent = Some("5".to_string());
+        let label = create_label(&store, None, " Pads ".to_string(), parent).unwrap();
+
+        assert_eq!(label.id, "6");
+        assert_eq!(label.name, "Pads");
+        assert!(store.label_exists("6").unwrap());
+    }
+
+    #[test]
+    fn label_parent_must_exist() {
+        let store = fixtures::store();
+        let res = create_label(&store, None, "Pads".to_string(), Some("99".to_string()));
+
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+    }
+
+    // Passes if a label can't be moved under one of its descendants
+    #[test]
+    fn label_cycle_prevented() {
+        let store = fixtures::store();
+        let res = update_label(&store, "1".to_string(), None, Some("3".to_string()));
+
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+        assert_eq!(store.get_label("1").unwrap().parent, "0");
+    }
+
+    #[test]
+    fn label_in_use_not_deleted() {
+        let store = fixtures::store();
+        update_request_labels(&store, "req-1".to_string(), "4".to_string(), "tester").unwrap();
+
+        let res = delete_label(&store, "4".to_string(), false);
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+
+        let res = delete_label(&store, "2".to_string(), false);
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+    }
+
+    #[test]
+    fn label_cascade_deleted() {
+        let store = fixtures::store();
+        update_request_labels(&store, "req-1".to_string(), "3".to_string(), "tester").unwrap();
+
+        let response = delete_label(&store, "1".to_string(), true).unwrap();
+
+        let deleted: Vec<String> = response
+            .references
+            .into_iter()
+            .map(|r| r.reference)
+            .collect();
+        assert_eq!(deleted, vec!["1", "2", "3", "4"]);
+        let request = get_request(&store, "req-1", Some("labels")).unwrap();
+        assert!(request.labels.unwrap().is_empty());
+    }
+
+    #[test]
+    fn label_tree_nested() {
+        let store = fixtures::store();
+        let tree = get_label_tree(&store, None, None).unwrap();
+
+        let names: Vec<&str> = tree.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(names, vec!["Brakes", "Engine"]);
+
+        let engine = &tree[1];
+        assert_eq!(engine.child_count, 2);
+        let radiator = &engine.children[0].children[0];
+        assert_eq!(radiator.path, vec!["Engine", "Cooling", "Radiator"]);
+        assert_eq!(radiator.child_count, 0);
+    }
+
+    // Passes if a rooted tree keeps the full path and stops at the requested depth
+    #[test]
+    fn label_tree_rooted_depth() {
+        let store = fixtures::store();
+        let tree = get_label_tree(&store, Some("2".to_string()), Some(1)).unwrap();
+
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].path, vec!["Engine", "Cooling"]);
+        assert_eq!(tree[0].child_count, 1);
+        assert!(tree[0].children.is_empty());
+
+        let res = get_label_tree(&store, Some("99".to_string()), None);
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+    }
+
+    #[test]
+    fn import_dry_run_diff() {
+        let store = fixtures::store();
+        let body = br#"[
+            {"id": "1", "name": "Engine", "parent": "0"},
+            {"id": "2", "name": "Cooling system", "parent": "1"},
+            {"id": "3", "name": "Radiator", "parent": "1"},
+            {"id": "5", "name": "Brakes", "parent": "0"},
+            {"id": "6", "name": "Pads", "parent": "5"}
+        ]"#;
+
+        let diff = import_labels(&store, body, LabelFormat::Json, true).unwrap();
+
+        assert!(!diff.applied);
+        assert_eq!(diff.added[0].id, "6");
+        assert_eq!(diff.renamed[0].to, "Cooling system");
+        assert_eq!(diff.reparented[0].id, "3");
+        assert_eq!(diff.deleted[0].id, "4");
+        assert!(store.label_exists("4").unwrap());
+    }
+
+    #[test]
+    fn import_rejects_orphans_and_cycles() {
+        let store = fixtures::store();
+        let orphan = b"id,name,parent\n1,Engine,0\n2,Cooling,9\n";
+        let cycle = b"id,name,parent\n1,Engine,2\n2,Cooling,1\n";
+
+        let res = import_labels(&store, orphan, LabelFormat::Csv, false);
+        assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+
+        let res = import_labels(&store, cycle, LabelFormat::Csv, false);
+        assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+        assert_eq!(store.get_all_labels().unwrap().len(), 5);
+    }
+
+    // Passes if an exported taxonomy imports back without changes
+    #[test]
+    fn export_import_round_trip() {
+        let store = fixtures::store();
+        let csv = export_labels(&store, LabelFormat::Csv).unwrap();
+
+        assert!(csv.starts_with("id,name,parent\n1,Engine,0\n"));
+        let diff = import_labels(&store, csv.as_bytes(), LabelFormat::Csv, false).unwrap();
+        assert!(diff.applied);
+        assert!(diff.added.is_empty() && diff.deleted.is_empty());
+    }
+
+    #[test]
+    fn import_keeps_labels_in_use() {
+        let store = fixtures::store();
+        update_request_labels(&store, "req-1".to_string(), "5".to_string(), "tester").unwrap();
+
+        let res = import_labels(
+            &store,
+            b"id,name,parent\n1,Engine,0\n",
+            LabelFormat::Csv,
+            false,
+        );
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+        assert!(store.label_exists("5").unwrap());
+    }
+
+    // Passes if labels are resolved to their paths, flagging the ones chosen explicitly
+    #[test]
+    fn request_labels_resolved() {
+        let store = fixtures::store();
+        update_request_labels(&store, "req-1".to_string(), "3".to_string(), "tester").unwrap();
+        update_request_labels(&store, "req-1".to_string(), "4".to_string(), "tester").unwrap();
+
+        let request = get_request(&store, "req-1", None).unwrap();
+
+        let labels: Vec<(String, bool)> = request
+            .labels
+            .unwrap()
+            .into_iter()
+            .map(|label| (label.path, label.leaf))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ("Engine".to_string(), false),
+                ("Engine > Cooling".to_string(), false),
+                ("Engine > Cooling > Radiator".to_string(), true),
+                ("Engine > Ignition".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn request_aggregate_loaded() {
+        let store = fixtures::store();
+        let vehicle = VehicleData::new("HONDA", "CIVIC", "2003", "1HGCM82633A004352");
+        let requestor = Requestor {
+            user_id: "user-1".to_string(),
+        };
+        let mut request = store.get_request_by_id("req-1").unwrap();
+        request.vehicle = Some(vehicle);
+        request.requestor = Some(requestor);
+        store.insert_request(request);
+
+        let request = get_request(&store, "req-1", None).unwrap();
+        assert_eq!(request.vehicle.unwrap().make.as_deref(), Some("HONDA"));
+        assert_eq!(request.requestor.unwrap().user_id, "user-1");
+        assert!(request.details.is_none());
+        assert!(request.labels.unwrap().is_empty());
+    }
+
+    // Passes if only the included sub-resources are loaded
+    #[test]
+    fn request_include_filters() {
+        let store = fixtures::store();
+        let vehicle = VehicleData::new("HONDA", "CIVIC", "2003", "1HGCM82633A004352");
+        let mut request = store.get_request_by_id("req-1").unwrap();
+        request.vehicle = Some(vehicle);
+        store.insert_request(request);
+
+        let request = get_request(&store, "req-1", Some("labels")).unwrap();
+        assert!(request.vehicle.is_none());
+        assert!(request.labels.is_some());
+
+        let res = get_request(&store, "req-1", Some("vehicle,quotes"));
+        assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+
+        let res = get_request(&store, "req-404", None);
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+    }
+
+    // Passes if a request can't be classified until a leaf label and the year are set
+    #[test]
+    fn classification_guards() {
+        let store = fixtures::store();
+
+        let res = classification_completed(&store, "req-1".to_string(), "tester");
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+
+        // Cooling has children so it isn't enough
+        update_request_labels(&store, "req-1".to_string(), "2".to_string(), "tester").unwrap();
+        select_year(&store, "req-1".to_string(), "2003", "tester").unwrap();
+        let res = classification_completed(&store, "req-1".to_string(), "tester");
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+
+        update_request_labels(&store, "req-1".to_string(), "3".to_string(), "tester").unwrap();
+        let transition = classification_completed(&store, "req-1".to_string(), "tester").unwrap();
+        assert_eq!(transition.from, RequestStatus::Pending);
+        assert_eq!(transition.to, RequestStatus::Classified);
+
+        let request = get_request(&store, "req-1", Some("")).unwrap();
+        assert_eq!(request.classified, RequestStatus::Classified);
+    }
+
+    // Passes if labels and year of a classified request can't be changed anymore
+    #[test]
+    fn classified_request_locked() {
+        let store = fixtures::store();
+        update_request_labels(&store, "req-1".to_string(), "3".to_string(), "tester").unwrap();
+        select_year(&store, "req-1".to_string(), "2003", "tester").unwrap();
+        classification_completed(&store, "req-1".to_string(), "tester").unwrap();
+
+        let res = remove_request_labels(&store, "req-1".to_string(), "0".to_string(), "tester");
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+        let res = update_request_labels(&store, "req-1".to_string(), "4".to_string(), "tester");
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+        let res = select_year(&store, "req-1".to_string(), "2004", "tester");
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+
+        let request = get_request(&store, "req-1", None).unwrap();
+        assert_eq!(request.labels.unwrap().len(), 3);
+        assert_eq!(request.vehicle.unwrap().year.as_deref(), Some("2003"));
+    }
+
+    // Passes if illegal moves and unknown statuses are rejected without changes
+    #[test]
+    fn illegal_transitions() {
+        let store = fixtures::store();
+
+        let res = transition_request(&store, "req-1".to_string(), "QUOTED", "tester");
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+
+        let res = transition_request(&store, "req-1".to_string(), "ARCHIVED", "tester");
+        assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+
+        let res = transition_request(&store, "req-404".to_string(), "IN_REVIEW", "tester");
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+
+        transition_request(&store, "req-1".to_string(), "REJECTED", "tester").unwrap();
+        let res = transition_request(&store, "req-1".to_string(), "IN_REVIEW", "tester");
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+
+        let transition =
+            transition_request(&store, "req-1".to_string(), "PENDING", "tester").unwrap();
+        assert_eq!(transition.from, RequestStatus::Rejected);
+    }
+
+    // Passes if every mutation is recorded in order with its actor and values
+    #[test]
+    fn history_recorded() {
+        let store = fixtures::store();
+        update_request_labels(&store, "req-1".to_string(), "3".to_string(), "ana").unwrap();
+        remove_request_labels(&store, "req-1".to_string(), "2".to_string(), "ana").unwrap();
+        select_year(&store, "req-1".to_string(), "2003", "luis").unwrap();
+        transition_request(&store, "req-1".to_string(), "IN_REVIEW", "luis").unwrap();
+
+        let history = get_request_history(&store, "req-1").unwrap();
+        let actions: Vec<RequestAction> = history.iter().map(|event| event.action).collect();
+        assert_eq!(
+            actions,
+            vec![
+                RequestAction::LabelAdded,
+                RequestAction::LabelRemoved,
+                RequestAction::YearSelected,
+                RequestAction::StatusChanged,
+            ]
+        );
+
+        assert_eq!(history[0].before, "");
+        assert_eq!(history[0].after, "1,2,3");
+        assert_eq!(history[1].before, "1,2,3");
+        assert_eq!(history[1].after, "1");
+        assert_eq!(history[2].after, "2003");
+        assert_eq!(history[3].before, "PENDING");
+        assert_eq!(history[3].after, "IN_REVIEW");
+        assert_eq!(history[3].actor, "luis");
+
+        let request = get_request(&store, "req-1", Some("history")).unwrap();
+        assert_eq!(request.history.unwrap(), history);
+    }
+
+    // Passes if failed mutations leave no events behind
+    #[test]
+    fn history_skips_failures() {
+        let store = fixtures::store();
+        let res = update_request_labels(&store, "req-1".to_string(), "99".to_string(), "ana");
+        assert!(res.is_err());
+        let res = transition_request(&store, "req-1".to_string(), "QUOTED", "ana");
+        assert!(res.is_err());
+
+        assert!(get_request_history(&store, "req-1").unwrap().is_empty());
+
+        let res = get_request_history(&store, "req-404");
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+    }
+
+    // Passes if requests are handed out oldest first, once each, until the queue is empty
+    #[test]
+    fn claims_oldest_pending() {
+        let store = fixtures::store();
+        let older = PartRequest::new(
+            "req-0",
+            "WHATSAPP",
+            "tracker-0",
+            "1640995200000",
+            RequestStatus::Pending,
+        );
+        store.insert_request(older);
+
+        let first = claim_next_request(&store, "ana", None).unwrap().unwrap();
+        let second = claim_next_request(&store, "luis", None).unwrap().unwrap();
+        assert_eq!(first.request.id, "req-0");
+        assert_eq!(first.claim.owner, "ana");
+        assert_eq!(second.request.id, "req-1");
+
+        assert!(claim_next_request(&store, "eva", None).unwrap().is_none());
+
+        let res = claim_next_request(&store, "eva", Some(10));
+        assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+    }
+
+    // Passes if only the owner changes a claimed request, renews or releases its claim
+    #[test]
+    fn claim_owner_only() {
+        let store = fixtures::store();
+        claim_next_request(&store, "ana", None).unwrap().unwrap();
+
+        let res = update_request_labels(&store, "req-1".to_string(), "3".to_string(), "luis");
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+        let res = renew_claim(&store, "req-1".to_string(), "luis", None);
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+        update_request_labels(&store, "req-1".to_string(), "3".to_string(), "ana").unwrap();
+
+        let claim = renew_claim(&store, "req-1".to_string(), "ana", Some(60000)).unwrap();
+        assert_eq!(claim.owner, "ana");
+
+        release_claim(&store, "req-1".to_string(), "ana").unwrap();
+        let res = release_claim(&store, "req-1".to_string(), "ana");
+        assert!(matches!(res, Err(ClassificatorError::NotFound(_))));
+
+        let claimed = claim_next_request(&store, "luis", None).unwrap().unwrap();
+        assert_eq!(claimed.request.id, "req-1");
+    }
+
+    // Passes if expired claims go back to the queue and finished requests release theirs
+    #[test]
+    fn claim_expires_and_completes() {
+        let store = fixtures::store();
+
+        // Handlers don't accept ttls this short, the store is used directly
+        store.claim_next_request("ana", 1).unwrap().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(store.get_claim("req-1").unwrap().is_none());
+
+        let claimed = claim_next_request(&store, "luis", None).unwrap().unwrap();
+        assert_eq!(claimed.claim.owner, "luis");
+
+        transition_request(&store, "req-1".to_string(), "REJECTED", "luis").unwrap();
+        assert!(store.get_claim("req-1").unwrap().is_none());
+    }
+
+    fn search_store() -> InMemoryStore {
+        let store = fixtures::store();
+
+        for (id, origin, timestamp, make) in [
+            ("req-2", "WHATSAPP", "1672617600000", "Toyota"),
+            ("req-3", "EMAIL", "1672704000000", "Toyota"),
+            ("req-4", "WHATSAPP", "1672790400000", "Nissan"),
+        ] {
+            let mut request =
+                PartRequest::new(id, origin, "tracker", timestamp, RequestStatus::Pending);
+            request.vehicle = Some(VehicleData {
+                make: Some(make.to_string()),
+                model: Some("Corolla".to_string()),
+                year: Some("2015".to_string()),
+                ..VehicleData::default()
+            });
+            store.insert_request(request);
+        }
+
+        store
+    }
+
+    // Passes if filters narrow the results while total counts every match outside the page
+    #[test]
+    fn search_filters_and_pages() {
+        let store = search_store();
+
+        let query = RequestListQuery {
+            limit: Some(2),
+            ..RequestListQuery::default()
+        };
+        let page = search_requests(&store, query).unwrap();
+        let ids: Vec<&str> = page.requests.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(page.total, 4);
+        assert_eq!(ids, vec!["req-1", "req-2"]);
+
+        let query = RequestListQuery {
+            origin: Some("whatsapp".to_string()),
+            make: Some("TOYOTA".to_string()),
+            ..RequestListQuery::default()
+        };
+        let page = search_requests(&store, query).unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.requests[0].id, "req-2");
+
+        let query = RequestListQuery {
+            from: Some(1672617600000),
+            to: Some(1672704000000),
+            sort: SortOrder::Desc,
+            offset: Some(1),
+            ..RequestListQuery::default()
+        };
+        let page = search_requests(&store, query).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.requests[0].id, "req-2");
+
+        transition_request(&store, "req-4".to_string(), "REJECTED", "tester").unwrap();
+        let query = RequestListQuery {
+            status: Some("ANY".to_string()),
+            make: Some("Nissan".to_string()),
+            ..RequestListQuery::default()
+        };
+        assert_eq!(search_requests(&store, query).unwrap().total, 1);
+    }
+
+    #[test]
+    fn search_invalid_query() {
+        let store = search_store();
+
+        for query in [
+            RequestListQuery {
+                limit: Some(0),
+                ..RequestListQuery::default()
+            },
+            RequestListQuery {
+                from: Some(2),
+                to: Some(1),
+                ..RequestListQuery::default()
+            },
+            RequestListQuery {
+                status: Some("DONE?".to_string()),
+                ..RequestListQuery::default()
+            },
+        ] {
+            let res = search_requests(&store, query);
+            assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+        }
+    }
+
+    // Passes if every word has to match some field and matched words are highlighted
+    #[test]
+    fn text_search_highlights() {
+        let store = search_store();
+        let details = RequestDetails {
+            description: Some(String::from("Radiador para Corolla, lado izquierdo")),
+            attached_files: None,
+        };
+        let mut request = store.get_request_by_id("req-3").unwrap();
+        request.details = Some(details);
+        store.insert_request(request);
+        update_request_labels(&store, "req-3".to_string(), "3".to_string(), "tester").unwrap();
+
+        let query = TextSearchQuery {
+            q: String::from("radiador toyota"),
+            ..TextSearchQuery::default()
+        };
+        let page = search_request_text(&store, query).unwrap();
+        assert_eq!(page.total, 1);
+
+        let found = &page.matches[0];
+        assert_eq!(found.request_id, "req-3");
+        assert_eq!(
+            found.description.as_deref(),
+            Some("<em>Radiador</em> para Corolla, lado izquierdo")
+        );
+        assert_eq!(found.make.as_deref(), Some("<em>Toyota</em>"));
+        assert_eq!(found.labels, vec!["1", "2", "3"]);
+
+        let query = TextSearchQuery {
+            q: String::from("corolla"),
+            limit: Some(1),
+            ..TextSearchQuery::default()
+        };
+        let page = search_request_text(&store, query).unwrap();
+        assert_eq!((page.total, page.matches.len()), (3, 1));
+
+        let query = TextSearchQuery {
+            q: String::from(" ** "),
+            ..TextSearchQuery::default()
+        };
+        let res = search_request_text(&store, query);
+        assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+    }
+
+    // Passes if submissions are only accepted for the web form and email systems
+    #[test]
+    fn form_submission_origin() {
+        let store = fixtures::store();
+
+        for origin_system in ["3", "9", "web"] {
+            let res = form_submission_received(&store, &registry(), submission(origin_system));
+            assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+        }
+
+        let mut empty_reference = submission("4");
+        empty_reference.reference = String::from(" ");
+        let res = form_submission_received(&store, &registry(), empty_reference);
+        assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+    }
+
+    // Passes if a submission without VIN doesn't create a request
+    #[test]
+    fn form_submission_without_vin() {
+        let store = fixtures::store();
+        let mut no_vin = submission("5");
+        no_vin.vin = String::from("  ");
+
+        let res = form_submission_received(&store, &registry(), no_vin);
+        assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+
+        let created = store.find_request_by_reference("EMAIL", "form-77");
+        assert_eq!(created.unwrap(), None);
+    }
+
+    fn walk_in() -> NewPartRequest {
+        NewPartRequest {
+            reference: Some(String::from("T-1042")),
+            requestor_id: String::from("u-17"),
+            vin: Some(String::from("1hgcm82633a004352")),
+            description: Some(String::from("Radiador")),
+            ..NewPartRequest::default()
+        }
+    }
+
+    // Passes if the request is stored with its vehicle, details and requestor
+    #[test]
+    fn walk_in_created() {
+        let store = fixtures::store();
+        let created = create_part_request(&store, walk_in()).unwrap();
+
+        let request = get_request(&store, &created.id, None).unwrap();
+        assert_eq!(request.origin, "COUNTER");
+        assert_eq!(request.origin_reference, "T-1042");
+        assert_eq!(request.classified, RequestStatus::Pending);
+
+        let vehicle = request.vehicle.unwrap();
+        assert_eq!(vehicle.vin.as_deref(), Some("1HGCM82633A004352"));
+        assert_eq!(vehicle.year.as_deref(), Some("2003"));
+        assert_eq!(vehicle.year_source, Some(YearSource::Inferred));
+        // Make and model the counter didn't fill in are taken from the VIN
+        assert_eq!(vehicle.make.as_deref(), Some("Honda"));
+        assert_eq!(vehicle.model.as_deref(), Some("Accord"));
+        assert_eq!(vehicle.mismatch, None);
+        assert_eq!(request.requestor.unwrap().user_id, "u-17");
+        let details = request.details.unwrap();
+        assert_eq!(details.description.as_deref(), Some("Radiador"));
+
+        let res = create_part_request(&store, walk_in());
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+    }
+
+    // Passes if a vehicle without VIN is accepted with its make, model and year
+    #[test]
+    fn walk_in_without_vin() {
+        let store = fixtures::store();
+        let new_request = NewPartRequest {
+            reference: None,
+            vin: None,
+            make: Some(String::from("Toyota")),
+            model: Some(String::from("Corolla")),
+            year: Some(String::from("2015")),
+            ..walk_in()
+        };
+        let created = create_part_request(&store, new_request).unwrap();
+
+        assert_eq!(created.origin_reference, created.id);
+        let vehicle = created.vehicle.unwrap();
+        assert_eq!((vehicle.vin, vehicle.year.as_deref()), (None, Some("2015")));
+    }
+
+    #[test]
+    fn walk_in_invalid() {
+        let store = fixtures::store();
+        let invalid = [
+            NewPartRequest {
+                requestor_id: String::from(" "),
+                ..walk_in()
+            },
+            NewPartRequest {
+                vin: Some(String::from("1HGCM82633A00435")),
+                ..walk_in()
+            },
+            NewPartRequest {
+                vin: None,
+                make: Some(String::from("Toyota")),
+                ..walk_in()
+            },
+            NewPartRequest {
+                year: Some(String::from("20015")),
+                ..walk_in()
+            },
+        ];
+
+        for new_request in invalid {
+            let res = create_part_request(&store, new_request);
+            assert!(matches!(res, Err(ClassificatorError::Validation(_))));
+        }
+
+        let created = store.find_request_by_reference("COUNTER", "T-1042");
+        assert_eq!(created.unwrap(), None);
+    }
+
+    // Passes if ambiguous years are left to the classifier and its selection is recorded
+    #[test]
+    fn year_source_recorded() {
+        let store = fixtures::store();
+        let new_request = NewPartRequest {
+            vin: Some(String::from("JHMCM8263AA004352")),
+            ..walk_in()
+        };
+        let created = create_part_request(&store, new_request).unwrap();
+
+        let vehicle = created.vehicle.unwrap();
+        assert_eq!(vehicle.year.as_deref(), Some("1980,2010"));
+        assert_eq!(vehicle.year_source, None);
+
+        update_request_labels(&store, created.id.clone(), "3".to_string(), "tester").unwrap();
+        let res = classification_completed(&store, created.id.clone(), "tester");
+        assert!(matches!(res, Err(ClassificatorError::Conflict(_))));
+
+        select_year(&store, created.id.clone(), "2010", "tester").unwrap();
+        let request = get_request(&store, &created.id, None).unwrap();
+        let vehicle = request.vehicle.unwrap();
+        assert_eq!(vehicle.year.as_deref(), Some("2010"));
+        assert_eq!(vehicle.year_source, Some(YearSource::Manual));
+
+        // Years given at the counter are set manually
+        let new_request = NewPartRequest {
+            reference: Some(String::from("T-1043")),
+            year: Some(String::from("2011")),
+            ..walk_in()
+        };
+        let created = create_part_request(&store, new_request).unwrap();
+        let vehicle = created.vehicle.unwrap();
+        assert_eq!(vehicle.year.as_deref(), Some("2011"));
+        assert_eq!(vehicle.year_source, Some(YearSource::Manual));
+    }
+}
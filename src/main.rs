@@ -1,156 +1,498 @@
-use crate::request_structs::{LabelUpdate, YearSelection};
+use crate::auth::{Authentication, Identity, Role};
+use crate::errors::ClassificatorError;
+use crate::redis::pool::{create_pool, PoolConfig};
+use crate::redis::store::RedisStore;
+use crate::request_structs::{
+    ClaimQuery, FormSubmission, LabelChanges, LabelDeletion, LabelExport, LabelFormat, LabelImport,
+    LabelTreeQuery, LabelUpdate, NewLabel, NewPartRequest, RequestListQuery, RequestQuery,
+    StatusChange, TextSearchQuery, YearSelection,
+};
+use crate::structs::SourceRegistry;
 use actix_cors::Cors;
-use crate::structs::classification::Label;
 use actix_web::middleware::Logger;
-use actix_web::{delete, get, post, put, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{delete, get, patch, post, put, web, App, HttpResponse, HttpServer, Responder};
+use fizzy_commons::redis::client::create_client;
 use fizzy_commons::shared_structs::MessageLog;
-use handlers::{get_all_labels};
 use log::debug;
 
+mod auth;
+mod errors;
 mod handlers;
 mod helpers;
 mod redis;
 mod request_structs;
+mod storage;
 mod structs;
+mod vin;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
 
-    HttpServer::new(|| {
-    let cors = Cors::default()
-        .allow_any_origin()
-        .allowed_methods(vec!["GET", "POST", "DELETE", "PUT"])
-        .max_age(3600);
+    // Connections are shared by every worker through the pool
+    let client = create_client().expect("Redis client couldnt be created.");
+    let pool = create_pool(client, &PoolConfig::from_env())
+        .expect("Redis connection pool couldnt be created.");
+
+    let store = web::Data::new(RedisStore::new(pool.clone()));
+
+    // `migrate-indexes [--all]` rebuilds the indexes which schema changed and exits,
+    // `backfill-requests` copies the indexed fields of older requests and exits
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("migrate-indexes") => {
+            let force = args.iter().any(|arg| arg == "--all");
+            return migrate_indexes(&store, force);
+        }
+        Some("backfill-requests") => return backfill_requests(&store),
+        Some(command) => {
+            let err = format!(
+                "Unknown command '{}', expected migrate-indexes or backfill-requests",
+                command
+            );
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, err));
+        }
+        None => {}
+    }
+
+    store
+        .verify_indexes()
+        .expect("Search indexes couldnt be verified.");
+    vin::catalog::init_from_env().expect("VIN catalog couldnt be loaded.");
+    let sources = web::Data::new(SourceRegistry::new(pool));
+    let authentication = Authentication::from_env().expect("Authentication couldnt be configured.");
+
+    HttpServer::new(move || {
+        let cors = Cors::default()
+            .allow_any_origin()
+            .allowed_methods(vec!["GET", "POST", "DELETE", "PUT", "PATCH"])
+            .allow_any_header()
+            .max_age(3600);
 
         App::new()
+            .app_data(store.clone())
+            .app_data(sources.clone())
+            // Malformed query strings and bodies are reported as validation errors
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _| ClassificatorError::Validation(err.to_string()).into()),
+            )
+            .app_data(
+                web::JsonConfig::default()
+                    .error_handler(|err, _| ClassificatorError::Validation(err.to_string()).into()),
+            )
+            .wrap(authentication.clone())
             .wrap(Logger::new("%U"))
             .wrap(cors)
             .service(incoming_messages)
+            .service(incoming_form)
+            // Registered before /request/{request_id} so "search" isn't taken as an id
+            .service(search_request_text)
             .service(get_request)
+            .service(create_part_request)
+            .service(request_history)
             .service(year_selection)
             .service(classification_completed)
+            .service(transition_request)
             .service(pending_requests)
+            .service(claim_next_request)
+            .service(renew_claim)
+            .service(release_claim)
             .service(append_label)
             .service(remove_label)
             .service(get_labels)
+            .service(get_label_tree)
+            .service(export_labels)
+            .service(import_labels)
             .service(health)
             .service(get_child_labels)
+            .service(create_label)
+            .service(update_label)
+            .service(delete_label)
     })
     .bind(("0.0.0.0", 8080))?
     .run()
     .await
 }
 
+fn migrate_indexes(store: &RedisStore, force: bool) -> std::io::Result<()> {
+    let to_io = |err: ClassificatorError| std::io::Error::other(err.to_string());
+
+    for (index, migration) in store.migrate_indexes(force).map_err(to_io)? {
+        println!("{}: {:?}", index, migration);
+    }
+
+    Ok(())
+}
+
+// Requests stored before the indexed fields and reference keys were added
+fn backfill_requests(store: &RedisStore) -> std::io::Result<()> {
+    let to_io = |err: ClassificatorError| std::io::Error::other(err.to_string());
+
+    let updated = store.backfill_request_fields().map_err(to_io)?;
+    println!("Fields and references updated for {} requests", updated);
+
+    Ok(())
+}
+
 #[get("/health")]
 async fn health() -> impl Responder {
     "OK"
 }
 
 #[get("/label/all")]
-async fn get_labels() -> impl Responder {
-    let response = handlers::get_all_labels();
+async fn get_labels(store: web::Data<RedisStore>) -> Result<HttpResponse, ClassificatorError> {
+    let response = web::block(move || handlers::get_all_labels(store.get_ref())).await??;
 
-    match response {
-        Ok(response) => HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()),
-        Err(err) => HttpResponse::InternalServerError().body(serde_json::to_string(&err).unwrap()),
-    }
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
 }
 
-
 #[post("/incoming")]
-async fn incoming_messages(log: web::Json<MessageLog>) -> impl Responder {
-    let response = handlers::new_request_received(log.0);
+async fn incoming_messages(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    sources: web::Data<SourceRegistry>,
+    log: web::Json<MessageLog>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Ingestion)?;
+    let response = web::block(move || {
+        handlers::new_request_received(store.get_ref(), sources.get_ref(), log.0)
+    })
+    .await??;
 
-    match response {
-        Ok(response) => HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()),
-        Err(response) => {
-            HttpResponse::InternalServerError().body(serde_json::to_string(&response).unwrap())
-        }
-    }
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[post("/incoming/form")]
+async fn incoming_form(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    sources: web::Data<SourceRegistry>,
+    submission: web::Json<FormSubmission>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Ingestion)?;
+    let response = web::block(move || {
+        handlers::form_submission_received(store.get_ref(), sources.get_ref(), submission.0)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[get("/request/search")]
+async fn search_request_text(
+    store: web::Data<RedisStore>,
+    query: web::Query<TextSearchQuery>,
+) -> Result<HttpResponse, ClassificatorError> {
+    let query = query.into_inner();
+    let response =
+        web::block(move || handlers::search_request_text(store.get_ref(), query)).await??;
+
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[post("/request")]
+async fn create_part_request(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    new_request: web::Json<NewPartRequest>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Counter)?;
+    let new_request = new_request.into_inner();
+    let response =
+        web::block(move || handlers::create_part_request(store.get_ref(), new_request)).await??;
+
+    Ok(HttpResponse::Created().body(serde_json::to_string(&response).unwrap()))
 }
 
 #[get("/request")]
-async fn pending_requests() -> impl Responder {
-    let response = handlers::get_pending_requests();
+async fn pending_requests(
+    store: web::Data<RedisStore>,
+    query: web::Query<RequestListQuery>,
+) -> Result<HttpResponse, ClassificatorError> {
+    let query = query.into_inner();
+    let response = web::block(move || handlers::search_requests(store.get_ref(), query)).await??;
 
-    match response {
-        Ok(response) => HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()),
-        Err(response) => HttpResponse::InternalServerError().body("error"),
-    }
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[get("/label/tree")]
+async fn get_label_tree(
+    store: web::Data<RedisStore>,
+    query: web::Query<LabelTreeQuery>,
+) -> Result<HttpResponse, ClassificatorError> {
+    let query = query.into_inner();
+    let response =
+        web::block(move || handlers::get_label_tree(store.get_ref(), query.root, query.depth))
+            .await??;
+
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[get("/label/export")]
+async fn export_labels(
+    store: web::Data<RedisStore>,
+    query: web::Query<LabelExport>,
+) -> Result<HttpResponse, ClassificatorError> {
+    let format = query.format;
+    let response = web::block(move || handlers::export_labels(store.get_ref(), format)).await??;
+
+    let content_type = match format {
+        LabelFormat::Json => "application/json",
+        LabelFormat::Csv => "text/csv",
+    };
+
+    Ok(HttpResponse::Ok().content_type(content_type).body(response))
+}
+
+#[post("/label/import")]
+async fn import_labels(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    query: web::Query<LabelImport>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Admin)?;
+    let response = web::block(move || {
+        handlers::import_labels(store.get_ref(), &body, query.format, query.dry_run)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
 }
 
 #[get("/label/{label_id}")]
-async fn get_child_labels(path: web::Path<String>) -> impl Responder {
-    let label_id = String::from(path.into_inner());
+async fn get_child_labels(
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ClassificatorError> {
+    let label_id = path.into_inner();
 
-    let response = handlers::get_labels(label_id);
+    let response = web::block(move || handlers::get_labels(store.get_ref(), label_id)).await??;
 
-    match response {
-        Ok(response) => HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()),
-        Err(err) => HttpResponse::InternalServerError().body(serde_json::to_string(&err).unwrap()),
-    }
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[post("/label")]
+async fn create_label(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    label: web::Json<NewLabel>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Admin)?;
+    let label = label.into_inner();
+    let response = web::block(move || {
+        handlers::create_label(store.get_ref(), label.id, label.name, label.parent)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[patch("/label/{label_id}")]
+async fn update_label(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+    changes: web::Json<LabelChanges>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Admin)?;
+    let label_id = path.into_inner();
+    let changes = changes.into_inner();
+    let response = web::block(move || {
+        handlers::update_label(store.get_ref(), label_id, changes.name, changes.parent)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[delete("/label/{label_id}")]
+async fn delete_label(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+    deletion: web::Query<LabelDeletion>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Admin)?;
+    let label_id = path.into_inner();
+    let response =
+        web::block(move || handlers::delete_label(store.get_ref(), label_id, deletion.cascade))
+            .await??;
+
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
 }
 
 // Path: (Request Id, Label Code)
 #[put("/request/{request_id}/labels")]
-async fn append_label(path: web::Path<String>, label: web::Query<LabelUpdate>) -> impl Responder {
+async fn append_label(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+    label: web::Query<LabelUpdate>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Classifier)?;
     debug!("{path:?} -> {label:?}");
     let request_id = String::from(&path.into_inner());
     let label_id = String::from(&label.label_id);
-    let response = handlers::update_request_labels(request_id, label_id);
+    let actor = identity.subject;
+    let response = web::block(move || {
+        handlers::update_request_labels(store.get_ref(), request_id, label_id, &actor)
+    })
+    .await??;
 
-    match response {
-        Ok(ok) => HttpResponse::Created().body(serde_json::to_string(&ok).unwrap()),
-        Err(err) => HttpResponse::InternalServerError().body(serde_json::to_string(&err).unwrap()),
-    }
+    Ok(HttpResponse::Created().body(serde_json::to_string(&response).unwrap()))
 }
 
 #[put("/request/{request_id}/done")]
-async fn classification_completed(path: web::Path<String>) -> impl Responder {
+async fn classification_completed(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Classifier)?;
     let request_id = String::from(&path.into_inner());
-    let response = handlers::classification_completed(request_id);
+    let actor = identity.subject;
+    let response =
+        web::block(move || handlers::classification_completed(store.get_ref(), request_id, &actor))
+            .await??;
 
-    match response {
-        Ok(_) => HttpResponse::Created().body(""),
-        Err(err) => HttpResponse::InternalServerError().body(serde_json::to_string(&err).unwrap()),
-    }
+    Ok(HttpResponse::Created().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[post("/request/{request_id}/transition")]
+async fn transition_request(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+    change: web::Json<StatusChange>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Classifier)?;
+    let request_id = path.into_inner();
+    let actor = identity.subject;
+    let response = web::block(move || {
+        handlers::transition_request(store.get_ref(), request_id, &change.status, &actor)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
 }
 
 #[put("/request/{request_id}/year")]
-async fn year_selection(path: web::Path<String>, year: web::Query<YearSelection>) -> impl Responder {
+async fn year_selection(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+    year: web::Query<YearSelection>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Classifier)?;
     let request_id = String::from(&path.into_inner());
-    let response = handlers::select_year(request_id, &year.year_selected);
+    let actor = identity.subject;
+    web::block(move || {
+        handlers::select_year(store.get_ref(), request_id, &year.year_selected, &actor)
+    })
+    .await??;
 
-    match response {
-        Ok(_) => HttpResponse::Created().body(""),
-        Err(err) => HttpResponse::InternalServerError().body(serde_json::to_string(&err).unwrap()),
-    }
+    Ok(HttpResponse::Created().body(""))
 }
 
 #[get("/request/{request_id}")]
-async fn get_request(path: web::Path<String>) -> impl Responder {
+async fn get_request(
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+    query: web::Query<RequestQuery>,
+) -> Result<HttpResponse, ClassificatorError> {
     let request_id = String::from(&path.into_inner());
-    let response = handlers::get_request(&request_id);
+    let include = query.into_inner().include;
+    let response =
+        web::block(move || handlers::get_request(store.get_ref(), &request_id, include.as_deref()))
+            .await??;
 
-    match response {
-        Ok(ok) => HttpResponse::Created().body(serde_json::to_string(&ok).unwrap()),
-        Err(err) => HttpResponse::InternalServerError().body(serde_json::to_string(&err).unwrap()),
-    }
+    Ok(HttpResponse::Created().body(serde_json::to_string(&response).unwrap()))
 }
+
 #[delete("/request/{request_id}/labels")]
-async fn remove_label(path: web::Path<String>, label: web::Query<LabelUpdate>) -> impl Responder {
+async fn remove_label(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+    label: web::Query<LabelUpdate>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Classifier)?;
     debug!("{path:?} -> {label:?}");
     let request_id = String::from(&path.into_inner());
     let label_id = String::from(&label.label_id);
-    let response = handlers::remove_request_labels(request_id, label_id);
+    let actor = identity.subject;
+    let response = web::block(move || {
+        handlers::remove_request_labels(store.get_ref(), request_id, label_id, &actor)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[post("/request/next")]
+async fn claim_next_request(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    query: web::Query<ClaimQuery>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Classifier)?;
+    let actor = identity.subject;
+    let response =
+        web::block(move || handlers::claim_next_request(store.get_ref(), &actor, query.ttl_ms))
+            .await??;
 
     match response {
-        Ok(ok) => HttpResponse::Created().body(serde_json::to_string(&ok).unwrap()),
-        Err(err) => HttpResponse::InternalServerError().body(serde_json::to_string(&err).unwrap()),
+        Some(claimed) => Ok(HttpResponse::Ok().body(serde_json::to_string(&claimed).unwrap())),
+        None => Ok(HttpResponse::NoContent().finish()),
     }
 }
 
+#[put("/request/{request_id}/claim")]
+async fn renew_claim(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+    query: web::Query<ClaimQuery>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Classifier)?;
+    let request_id = path.into_inner();
+    let actor = identity.subject;
+    let response = web::block(move || {
+        handlers::renew_claim(store.get_ref(), request_id, &actor, query.ttl_ms)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
+}
+
+#[delete("/request/{request_id}/claim")]
+async fn release_claim(
+    identity: Identity,
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ClassificatorError> {
+    identity.require(Role::Classifier)?;
+    let request_id = path.into_inner();
+    let actor = identity.subject;
+    web::block(move || handlers::release_claim(store.get_ref(), request_id, &actor)).await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/request/{request_id}/history")]
+async fn request_history(
+    store: web::Data<RedisStore>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ClassificatorError> {
+    let request_id = path.into_inner();
+    let response =
+        web::block(move || handlers::get_request_history(store.get_ref(), &request_id)).await??;
+
+    Ok(HttpResponse::Ok().body(serde_json::to_string(&response).unwrap()))
+}
+
 // #[post("/outgoing")]
 // async fn outgoing_message(log: web::Json<MessageLog>) -> impl Responder {
 //     let response = handlers::outgoing_messages(log.0);
@@ -1,427 +1,2537 @@
 pub mod classification {
-    use std::fmt::format;
-
+    use crate::errors::ClassificatorError;
+    use crate::redis::common::{get_hash, script_error};
+    use crate::redis::indexes::{LABEL_INDEX, REQUEST_INDEX, REQUEST_TEXT_INDEX};
+    use crate::redis::search::QueryBuilder;
+    use crate::storage::{RequestFilter, MAX_LABEL_DEPTH};
     use crate::structs::classification::Label;
-    use crate::structs::part_request::PartRequest;
-    use fizzy_commons::redis::client::create_client;
-    use fizzy_commons::redis::search::QueryBuilder;
+    use crate::structs::decode::documents;
+    use crate::structs::part_request::{
+        now_millis, PartRequest, RequestEvent, RequestMatch, RequestMatches, RequestPage,
+        RequestStatus,
+    };
     use log::{debug, error};
-    use redis::{Commands, RedisResult, Value};
+    use redis::{Connection, RedisResult, Script, Value};
+    use std::collections::HashMap;
+
+    // FT.SEARCH returns 10 documents unless told otherwise
+    pub const SEARCH_LIMIT: usize = 10000;
+
+    // KEYS: vehicle hash, request events stream, request hash. ARGV: request id, year, actor,
+    // timestamp, editable statuses.
+    // The year is copied to the request hash for request-search, and marked as set manually
+    const SELECT_YEAR: &str = r#"
+local request_id, year, actor, timestamp = ARGV[1], ARGV[2], ARGV[3], ARGV[4]
+
+if redis.call('EXISTS', KEYS[3]) == 0 then
+    return redis.error_reply("NOT_FOUND Part request '" .. request_id .. "' doesnt exist")
+end
+
+local status, editable = redis.call('HGET', KEYS[3], 'classified'), false
+for i = 5, #ARGV do
+    editable = editable or status == ARGV[i]
+end
+if not editable then
+    return redis.error_reply("CONFLICT Part request '" .. request_id .. "' is " ..
+        tostring(status) .. ", its year cant be changed")
+end
+
+local before = redis.call('HGET', KEYS[1], 'year') or ''
+redis.call('HSET', KEYS[1], 'year', year, 'year_source', 'MANUAL')
+redis.call('HSET', KEYS[3], 'year', year)
+redis.call('XADD', KEYS[2], '*', 'actor', actor, 'timestamp', timestamp,
+    'action', 'YEAR_SELECTED', 'before', before, 'after', year)
+return before
+"#;
+
+    // Statuses passed to the scripts changing labels or year, see RequestStatus::EDITABLE
+    fn editable_statuses() -> [&'static str; 2] {
+        RequestStatus::EDITABLE.map(|status| status.as_str())
+    }
 
-    pub fn year_selection(request_id: &str, year: &str) -> Result<(), String>{
+    pub fn year_selection(
+        con: &mut Connection,
+        request_id: &str,
+        year: &str,
+        actor: &str,
+    ) -> Result<(), ClassificatorError> {
+        let res: RedisResult<String> = Script::new(SELECT_YEAR)
+            .key(format!("part-request:{request_id}:vehicle"))
+            .key(format!("part-request:{request_id}:events"))
+            .key(format!("part-request:{request_id}"))
+            .arg(request_id)
+            .arg(year)
+            .arg(actor)
+            .arg(now_millis())
+            .arg(&editable_statuses())
+            .invoke(con);
+
+        let previous = res.map_err(script_error)?;
+        debug!("Request {request_id} year changed from '{previous}' to '{year}'");
 
-        let mut client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
+        Ok(())
+    }
 
-        let key = format!("part-request:{request_id}:vehicle");
-        let res: RedisResult<Value> = con.hset(key, "year", year);
+    // KEYS: request hash, request events stream. ARGV: request id, new status, actor, timestamp,
+    // accepted current statuses. Only changes the status if nobody moved the request since it
+    // was read
+    const SET_REQUEST_STATUS: &str = r#"
+local request_id, status, actor, timestamp = ARGV[1], ARGV[2], ARGV[3], ARGV[4]
+
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return redis.error_reply("NOT_FOUND Part request '" .. request_id .. "' doesnt exist")
+end
+
+local current = redis.call('HGET', KEYS[1], 'classified')
+for i = 5, #ARGV do
+    if current == ARGV[i] then
+        redis.call('HSET', KEYS[1], 'classified', status)
+        redis.call('XADD', KEYS[2], '*', 'actor', actor, 'timestamp', timestamp,
+            'action', 'STATUS_CHANGED', 'before', current, 'after', status)
+        return current
+    end
+end
+
+return redis.error_reply("CONFLICT Part request '" .. request_id .. "' status changed to " ..
+    tostring(current))
+"#;
+
+    pub fn set_request_status(
+        con: &mut Connection,
+        request_id: &str,
+        from: RequestStatus,
+        to: RequestStatus,
+        actor: &str,
+    ) -> Result<(), ClassificatorError> {
+        let script = Script::new(SET_REQUEST_STATUS);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(format!("part-request:{request_id}"))
+            .key(format!("part-request:{request_id}:events"))
+            .arg(request_id)
+            .arg(to.as_str())
+            .arg(actor)
+            .arg(now_millis())
+            .arg(from.as_str());
+
+        // Requests completed before the lifecycle existed
+        if from == RequestStatus::Classified {
+            invocation.arg("DONE");
+        }
+
+        let res: RedisResult<String> = invocation.invoke(con);
+        let previous = res.map_err(script_error)?;
+        debug!("Request {request_id} moved from {previous} to {to}");
 
-        if res.is_ok() { Ok(()) }else {Err(String::from(res.unwrap_err().to_string()))}
+        Ok(())
     }
 
-    pub fn complete_request(request_id: &str) -> Result<(), String>{
-        let mut client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
+    pub fn get_all_labels(con: &mut Connection) -> Result<Vec<Label>, ClassificatorError> {
+        debug!("Getting all labels");
+        let res: RedisResult<Vec<Label>> = redis::cmd("FT.SEARCH")
+            .arg(LABEL_INDEX.name)
+            .arg("*")
+            .arg("LIMIT")
+            .arg(0)
+            .arg(SEARCH_LIMIT)
+            .query(con);
+
+        Ok(res?)
+    }
 
-        let key = format!("part-request:{request_id}");
-        let res: RedisResult<Value> = con.hset(key, "classified", "DONE");
+    pub fn get_pending_classification_requests(
+        con: &mut Connection,
+    ) -> Result<Vec<PartRequest>, ClassificatorError> {
+        // Classifiers take requests through claims, see claims::claim_next_request
+        let res: RedisResult<Vec<PartRequest>> = redis::cmd("FT.SEARCH")
+            .arg(REQUEST_INDEX.name)
+            .arg("@classified:PENDING")
+            .arg("LIMIT")
+            .arg(0)
+            .arg(SEARCH_LIMIT)
+            .query(con);
+
+        res.map_err(|err| {
+            let error = format!("Error obtaining pending requests: {}", err);
+            error!("{}", error);
+            ClassificatorError::Storage(error)
+        })
+    }
+
+    // request-search query matching the filter, see search::QueryBuilder
+    pub fn request_query(filter: &RequestFilter) -> String {
+        let mut query = QueryBuilder::new();
 
-        if res.is_ok() { Ok(()) }else {Err(String::from(res.unwrap_err().to_string()))}
+        if let Some(status) = filter.status {
+            query = query.text("classified", status.as_str());
+        }
+
+        let tags = [
+            ("origin", &filter.origin),
+            ("make", &filter.make),
+            ("model", &filter.model),
+            ("year", &filter.year),
+            ("requestor", &filter.requestor),
+        ];
+        for (field, value) in tags {
+            if let Some(value) = value {
+                query = query.tag(field, value);
+            }
+        }
+
+        query.range("timestamp", filter.from, filter.to).build()
     }
 
-    pub fn get_all_labels() -> Result<Vec<Label>, String>{
-        
-        debug!("Getting all labels");
-        let mut query: QueryBuilder<Vec<Label>> = QueryBuilder::default();
+    pub fn search_requests(
+        con: &mut Connection,
+        filter: &RequestFilter,
+    ) -> Result<RequestPage, ClassificatorError> {
+        let query = request_query(filter);
+        debug!("Searching requests: {query}");
+
+        let res: RedisResult<Value> = redis::cmd("FT.SEARCH")
+            .arg(REQUEST_INDEX.name)
+            .arg(&query)
+            .arg("SORTBY")
+            .arg("timestamp")
+            .arg(filter.sort.as_str())
+            .arg("LIMIT")
+            .arg(filter.offset)
+            .arg(filter.limit)
+            .query(con);
+
+        let reply = match res {
+            Ok(reply) => reply,
+            Err(err) => {
+                let error = format!("Error searching requests: {}", err);
+                error!("{}", error);
+                return Err(ClassificatorError::Storage(error));
+            }
+        };
+
+        let (total, items) = search_reply(&reply)?;
+        let requests: Vec<PartRequest> = documents(items).map_err(|err| {
+            let error = format!("Error parsing searched requests: {}", err);
+            error!("{}", error);
+            ClassificatorError::Storage(error)
+        })?;
+
+        Ok(RequestPage {
+            total,
+            offset: filter.offset,
+            limit: filter.limit,
+            requests,
+        })
+    }
 
-        query
-            .index("label-parent-search".to_string());
+    pub fn search_request_text(
+        con: &mut Connection,
+        text: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<RequestMatches, ClassificatorError> {
+        let query = QueryBuilder::new().terms(text).build();
+        let fields = ["description", "vin", "make", "model"];
+
+        let res: RedisResult<Value> = redis::cmd("FT.SEARCH")
+            .arg(REQUEST_TEXT_INDEX.name)
+            .arg(&query)
+            .arg("RETURN")
+            .arg(fields.len() + 1)
+            .arg("request_id")
+            .arg(&fields)
+            .arg("HIGHLIGHT")
+            .arg("FIELDS")
+            .arg(fields.len())
+            .arg(&fields)
+            .arg(&["TAGS", "<em>", "</em>"])
+            .arg("LIMIT")
+            .arg(offset)
+            .arg(limit)
+            .query(con);
+
+        let reply = match res {
+            Ok(reply) => reply,
+            Err(err) => {
+                let error = format!("Error searching request text: {}", err);
+                error!("{}", error);
+                return Err(ClassificatorError::Storage(error));
+            }
+        };
 
-        let mut client = create_client().unwrap();
-        let res = query.all(&client);
+        let (total, items) = search_reply(&reply)?;
+        let mut matches: Vec<RequestMatch> = documents(items).map_err(|err| {
+            let error = format!("Error parsing request text matches: {}", err);
+            error!("{}", error);
+            ClassificatorError::Storage(error)
+        })?;
+
+        // Labels of every match so classifiers can reuse them
+        let mut pipe = redis::pipe();
+        for request_match in &matches {
+            pipe.smembers(format!("part-request:{}:labels", request_match.request_id));
+        }
+        let labels: Vec<Vec<String>> = pipe.query(con)?;
 
-        if res.is_err() {
-            return Err(res.unwrap_err().to_string());
+        for (request_match, mut labels) in matches.iter_mut().zip(labels) {
+            labels.sort();
+            request_match.labels = labels;
         }
 
-        Ok(res.unwrap())
+        Ok(RequestMatches {
+            total,
+            offset,
+            limit,
+            matches,
+        })
     }
 
-    pub fn get_pending_classification_requests() -> Result<Vec<PartRequest>, String> {
-        // TODO: Think on a concurrent solution
-        let mut query: QueryBuilder<Vec<PartRequest>> = QueryBuilder::default();
+    // Requests with any of the labels assigned and the ids of every label they have, found
+    // through the labels tag of request-search
+    pub fn requests_with_labels(
+        con: &mut Connection,
+        label_ids: &[String],
+    ) -> Result<Vec<(String, Vec<String>)>, ClassificatorError> {
+        if label_ids.is_empty() {
+            return Ok(vec![]);
+        }
 
-        query
-            .index("request-search".to_string())
-            .arg("classified".to_string(), "PENDING".to_string());
+        let query = QueryBuilder::new().any_tag("labels", label_ids).build();
+        let mut requests = vec![];
+
+        loop {
+            let res: RedisResult<Value> = redis::cmd("FT.SEARCH")
+                .arg(REQUEST_INDEX.name)
+                .arg(&query)
+                .arg("RETURN")
+                .arg(1)
+                .arg("labels")
+                .arg("LIMIT")
+                .arg(requests.len())
+                .arg(SEARCH_LIMIT)
+                .query(con);
+
+            let reply = res.map_err(|err| {
+                let error = format!("Error searching requests by label: {}", err);
+                error!("{}", error);
+                ClassificatorError::Storage(error)
+            })?;
+
+            let (total, items) = search_reply(&reply)?;
+            for document in items.chunks(2) {
+                match labelled_request(document) {
+                    Some(request) => requests.push(request),
+                    None => {
+                        let error = format!("Unexpected labelled request {:?}", document);
+                        error!("{}", error);
+                        return Err(ClassificatorError::Storage(error));
+                    }
+                }
+            }
 
-        let mut client = create_client().unwrap();
-        let res = query.search(&client);
+            if items.is_empty() || requests.len() >= total {
+                return Ok(requests);
+            }
+        }
+    }
 
-        if res.is_err() {
-            let error = format!(
-                "Error obtaining pending requests: {}",
-                res.as_ref().unwrap_err().to_string()
-            );
-            return Err(error);
+    // Request id and label ids of a request-search key followed by its labels field
+    fn labelled_request(document: &[Value]) -> Option<(String, Vec<String>)> {
+        let (key, fields) = match document {
+            [key, fields] => (key, fields),
+            _ => return None,
+        };
+        let key: String = redis::from_redis_value(key).ok()?;
+        let fields: HashMap<String, String> = redis::from_redis_value(fields).ok()?;
+
+        let request_id = key.strip_prefix(REQUEST_INDEX.prefix)?.to_string();
+        let labels = match fields.get("labels") {
+            Some(labels) => labels
+                .split(',')
+                .filter(|id| !id.is_empty())
+                .map(String::from)
+                .collect(),
+            None => vec![],
+        };
+
+        Some((request_id, labels))
+    }
+
+    // FT.SEARCH reply starts with the count of every match, followed by the page keys and
+    // documents
+    fn search_reply(reply: &Value) -> Result<(usize, &[Value]), ClassificatorError> {
+        match reply {
+            Value::Bulk(items) => match items.split_first() {
+                Some((Value::Int(total), items)) => Ok((*total as usize, items)),
+                _ => Ok((0, &items[..])),
+            },
+            _ => {
+                let error = format!("Unexpected search reply {:?}", reply);
+                error!("{}", error);
+                Err(ClassificatorError::Storage(error))
+            }
         }
+    }
+
+    // KEYS: request hash, request labels set, request events stream. ARGV: request id, label id,
+    // max depth, actor, timestamp, editable statuses.
+    // Adds the label and every ancestor up to the base labels, or nothing if any check fails.
+    // The assigned ids are copied to the request hash for request-search. Returns the hashes of
+    // the labels assigned, like ASSIGNED_LABELS
+    const ASSIGN_LABEL_TREE: &str = r#"
+local request_id, label, max_depth = ARGV[1], ARGV[2], tonumber(ARGV[3])
+local actor, timestamp = ARGV[4], ARGV[5]
+
+local function assigned_ids()
+    local ids = redis.call('SMEMBERS', KEYS[2])
+    table.sort(ids)
+    return table.concat(ids, ',')
+end
+
+local function assigned_labels()
+    local labels = {}
+    for _, id in ipairs(redis.call('SMEMBERS', KEYS[2])) do
+        local label = redis.call('HGETALL', 'part-label:' .. id)
+        if #label > 0 then
+            table.insert(labels, label)
+        end
+    end
+    return labels
+end
+
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return redis.error_reply("NOT_FOUND Part request '" .. request_id .. "' doesnt exist")
+end
+
+local status, editable = redis.call('HGET', KEYS[1], 'classified'), false
+for i = 6, #ARGV do
+    editable = editable or status == ARGV[i]
+end
+if not editable then
+    return redis.error_reply("CONFLICT Part request '" .. request_id .. "' is " ..
+        tostring(status) .. ", its labels cant be changed")
+end
+
+if redis.call('EXISTS', 'part-label:' .. label) == 0 then
+    return redis.error_reply("NOT_FOUND Label '" .. label .. "' doesnt exist")
+end
+
+if redis.call('SISMEMBER', KEYS[2], label) == 1 then
+    return redis.error_reply("CONFLICT Label '" .. label .. "' is already assigned to request")
+end
+
+local chain = {}
+local current = label
+while current and current ~= '0' do
+    if #chain >= max_depth then
+        return redis.error_reply("CYCLE Label '" .. label .. "' ancestors contain a cycle")
+    end
+
+    if redis.call('EXISTS', 'part-label:' .. current) == 0 then
+        return redis.error_reply("NOT_FOUND Parent label '" .. current .. "' doesnt exist")
+    end
+
+    table.insert(chain, current)
+    current = redis.call('HGET', 'part-label:' .. current, 'parent')
+end
+
+local before = assigned_ids()
+redis.call('SADD', KEYS[2], unpack(chain))
+local after = assigned_ids()
+redis.call('HSET', KEYS[1], 'labels', after)
+redis.call('XADD', KEYS[3], '*', 'actor', actor, 'timestamp', timestamp,
+    'action', 'LABEL_ADDED', 'before', before, 'after', after)
+return assigned_labels()
+"#;
+
+    // KEYS: request hash, request labels set, request events stream. ARGV: request id, label id,
+    // actor, timestamp, editable statuses.
+    // Removes the label and every assigned label below it and copies the ids left to the request
+    // hash, returns the hashes of the labels left
+    const REMOVE_LABEL_TREE: &str = r#"
+local request_id, label, actor, timestamp = ARGV[1], ARGV[2], ARGV[3], ARGV[4]
+
+local function assigned_ids()
+    local ids = redis.call('SMEMBERS', KEYS[2])
+    table.sort(ids)
+    return table.concat(ids, ',')
+end
+
+local function assigned_labels()
+    local labels = {}
+    for _, id in ipairs(redis.call('SMEMBERS', KEYS[2])) do
+        local label = redis.call('HGETALL', 'part-label:' .. id)
+        if #label > 0 then
+            table.insert(labels, label)
+        end
+    end
+    return labels
+end
+
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return redis.error_reply("NOT_FOUND Part request '" .. request_id .. "' doesnt exist")
+end
+
+local status, editable = redis.call('HGET', KEYS[1], 'classified'), false
+for i = 5, #ARGV do
+    editable = editable or status == ARGV[i]
+end
+if not editable then
+    return redis.error_reply("CONFLICT Part request '" .. request_id .. "' is " ..
+        tostring(status) .. ", its labels cant be changed")
+end
+
+-- Id 0 is a symbolic id for base labels
+if label ~= '0' and redis.call('SISMEMBER', KEYS[2], label) == 0 then
+    return redis.error_reply("CONFLICT Label '" .. label .. "' is not assigned to request")
+end
+
+local parents = {}
+for _, id in ipairs(redis.call('SMEMBERS', KEYS[2])) do
+    parents[id] = redis.call('HGET', 'part-label:' .. id, 'parent')
+end
+
+local before = assigned_ids()
+local removed = {[label] = true}
+local changed = true
+while changed do
+    changed = false
+    for id, parent in pairs(parents) do
+        if not removed[id] and parent and removed[parent] then
+            removed[id] = true
+            changed = true
+        end
+    end
+end
+
+for id, _ in pairs(removed) do
+    if id ~= '0' then
+        redis.call('SREM', KEYS[2], id)
+    end
+end
+
+local after = assigned_ids()
+redis.call('HSET', KEYS[1], 'labels', after)
+redis.call('XADD', KEYS[3], '*', 'actor', actor, 'timestamp', timestamp,
+    'action', 'LABEL_REMOVED', 'before', before, 'after', after)
+return assigned_labels()
+"#;
+
+    pub fn assign_label_tree(
+        con: &mut Connection,
+        request_id: &str,
+        label_id: &str,
+        actor: &str,
+    ) -> Result<Vec<Label>, ClassificatorError> {
+        let res: RedisResult<Vec<Label>> = Script::new(ASSIGN_LABEL_TREE)
+            .key(format!("part-request:{request_id}"))
+            .key(format!("part-request:{request_id}:labels"))
+            .key(format!("part-request:{request_id}:events"))
+            .arg(request_id)
+            .arg(label_id)
+            .arg(MAX_LABEL_DEPTH)
+            .arg(actor)
+            .arg(now_millis())
+            .arg(&editable_statuses())
+            .invoke(con);
+
+        let labels = res.map_err(script_error)?;
+        debug!("Labels assigned to {request_id}: {labels:?}");
+
+        Ok(labels)
+    }
 
-        Ok(res.unwrap())
+    pub fn remove_label_tree(
+        con: &mut Connection,
+        request_id: &str,
+        label_id: &str,
+        actor: &str,
+    ) -> Result<Vec<Label>, ClassificatorError> {
+        let res: RedisResult<Vec<Label>> = Script::new(REMOVE_LABEL_TREE)
+            .key(format!("part-request:{request_id}"))
+            .key(format!("part-request:{request_id}:labels"))
+            .key(format!("part-request:{request_id}:events"))
+            .arg(request_id)
+            .arg(label_id)
+            .arg(actor)
+            .arg(now_millis())
+            .arg(&editable_statuses())
+            .invoke(con);
+
+        let labels = res.map_err(script_error)?;
+        debug!("Labels left on {request_id}: {labels:?}");
+
+        Ok(labels)
     }
 
-    pub fn append_label(request_id: &str, label_code: &str) -> Result<(), String> {
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
+    pub fn get_label_childs(
+        con: &mut Connection,
+        label_id: &str,
+    ) -> Result<Vec<Label>, ClassificatorError> {
+        let res: RedisResult<Vec<Label>> = redis::cmd("FT.SEARCH")
+            .arg(LABEL_INDEX.name)
+            .arg(format!("@parent:{label_id}"))
+            .arg("LIMIT")
+            .arg(0)
+            .arg(SEARCH_LIMIT)
+            .query(con);
+
+        Ok(res?)
+    }
 
-        let key = format!("part-request:{request_id}:labels");
+    // Label and every label below it, one pipelined label-parent-search per tree level. Labels
+    // are returned once even if the parents contain a cycle
+    pub fn get_label_subtree(
+        con: &mut Connection,
+        label_id: &str,
+    ) -> Result<Vec<String>, ClassificatorError> {
+        let mut subtree = vec![label_id.to_string()];
+        let mut level = subtree.clone();
+
+        while !level.is_empty() {
+            let mut pipe = redis::pipe();
+            for parent in &level {
+                pipe.cmd("FT.SEARCH")
+                    .arg(LABEL_INDEX.name)
+                    .arg(format!("@parent:{parent}"))
+                    .arg("LIMIT")
+                    .arg(0)
+                    .arg(SEARCH_LIMIT);
+            }
+            let childs: Vec<Vec<Label>> = pipe.query(con)?;
+
+            level = vec![];
+            for child in childs.into_iter().flatten() {
+                if !subtree.contains(&child.id) {
+                    subtree.push(child.id.clone());
+                    level.push(child.id);
+                }
+            }
+        }
 
-        let res: RedisResult<Value> = con.sadd(key, label_code);
+        Ok(subtree)
+    }
 
-        if res.is_err() {
-            let err_msg = format!("Error appending label: {}", res.as_ref().unwrap_err());
-            error!("{}", &err_msg);
-            return Err(err_msg);
+    // Ids of the labels without childs, the childs of every label are counted in one round trip
+    pub fn leaf_labels(
+        con: &mut Connection,
+        label_ids: &[String],
+    ) -> Result<Vec<String>, ClassificatorError> {
+        if label_ids.is_empty() {
+            return Ok(vec![]);
         }
 
-        Ok(())
+        let mut pipe = redis::pipe();
+        for label_id in label_ids {
+            // Only the count of matches is returned
+            pipe.cmd("FT.SEARCH")
+                .arg(LABEL_INDEX.name)
+                .arg(format!("@parent:{label_id}"))
+                .arg("LIMIT")
+                .arg(0)
+                .arg(0);
+        }
+        let replies: Vec<Value> = pipe.query(con)?;
+
+        let mut leafs = vec![];
+        for (label_id, reply) in label_ids.iter().zip(replies) {
+            match reply {
+                Value::Bulk(items) => match items.first() {
+                    Some(Value::Int(0)) => leafs.push(label_id.clone()),
+                    Some(Value::Int(_)) => {}
+                    _ => return Err(unexpected_count(label_id)),
+                },
+                _ => return Err(unexpected_count(label_id)),
+            }
+        }
+
+        Ok(leafs)
+    }
+
+    fn unexpected_count(label_id: &str) -> ClassificatorError {
+        let error = format!("Unexpected reply counting the childs of label '{label_id}'");
+        error!("{}", error);
+        ClassificatorError::Storage(error)
     }
 
-    pub fn remove_label(request_id: &str, label_code: &str) -> Result<(), String> {
-        // Id 0 is a symbolic id for base labels
-        if label_code == "0" {
-            return Ok(());
+    // Events of the request stream, oldest first
+    pub fn get_request_history(
+        con: &mut Connection,
+        request_id: &str,
+    ) -> Result<Vec<RequestEvent>, ClassificatorError> {
+        let key = format!("part-request:{request_id}");
+
+        let (exists, events): (bool, Vec<RequestEvent>) = redis::pipe()
+            .exists(&key)
+            .xrange_all(format!("{key}:events"))
+            .query(con)?;
+
+        if !exists {
+            let err = format!("Part request '{}' doesnt exist", request_id);
+            return Err(ClassificatorError::NotFound(err));
         }
 
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
+        Ok(events)
+    }
 
-        let key = format!("part-request:{request_id}:labels");
+    pub fn get_label(con: &mut Connection, id: &str) -> Result<Label, ClassificatorError> {
+        let key = format!("part-label:{id}");
 
-        debug!("Removing label {label_code} on key {key}");
+        match get_hash(con, &key)? {
+            Some(label) => Ok(label),
+            None => Err(ClassificatorError::NotFound(format!(
+                "Label '{}' doesnt exist",
+                id
+            ))),
+        }
+    }
+}
 
-        let res: RedisResult<Value> = con.srem(key, label_code);
+pub mod search {
+    // Builds RediSearch queries one clause at a time, every clause must match. Values are escaped
+    // so they can't change the query
+    #[derive(Debug, Default, Clone)]
+    pub struct QueryBuilder {
+        clauses: Vec<String>,
+    }
 
-        if res.is_err() {
-            let err_msg = format!("Error removing label: {}", res.as_ref().unwrap_err());
-            error!("{}", &err_msg);
-            return Err(err_msg);
+    impl QueryBuilder {
+        pub fn new() -> Self {
+            QueryBuilder::default()
         }
 
-        let res2 = res.unwrap();
-        if let Value::Int(code) = res2 {
-            if code != 1 {
-                let err_msg =
-                    format!("Code {code}, error removing label, verify that label is assigned to request");
-                error!("{}", &err_msg);
-                return Err(err_msg);
+        // Word match on a TEXT field
+        pub fn text(mut self, field: &str, value: &str) -> Self {
+            self.clauses.push(format!("@{}:{}", field, escape(value)));
+            self
+        }
+
+        // Exact match on a TAG field, case insensitive
+        pub fn tag(mut self, field: &str, value: &str) -> Self {
+            self.clauses
+                .push(format!("@{}:{{{}}}", field, escape(value)));
+            self
+        }
+
+        // Match of any of the values on a TAG field
+        pub fn any_tag(mut self, field: &str, values: &[String]) -> Self {
+            let values: Vec<String> = values.iter().map(|value| escape(value)).collect();
+            self.clauses
+                .push(format!("@{}:{{{}}}", field, values.join(" | ")));
+            self
+        }
+
+        // Every word of the text on any TEXT field
+        pub fn terms(mut self, text: &str) -> Self {
+            let words: Vec<String> = text.split_whitespace().map(escape).collect();
+
+            if !words.is_empty() {
+                self.clauses.push(words.join(" "));
             }
-        };
+            self
+        }
 
-        Ok(())
+        // Inclusive range on a NUMERIC field, a missing bound leaves that side open
+        pub fn range(mut self, field: &str, min: Option<u64>, max: Option<u64>) -> Self {
+            if min.is_none() && max.is_none() {
+                return self;
+            }
+
+            let min = min.map_or(String::from("-inf"), |min| min.to_string());
+            let max = max.map_or(String::from("+inf"), |max| max.to_string());
+            self.clauses.push(format!("@{}:[{} {}]", field, min, max));
+            self
+        }
+
+        // Query with every clause, matches everything when there are none
+        pub fn build(&self) -> String {
+            if self.clauses.is_empty() {
+                return String::from("*");
+            }
+
+            self.clauses.join(" ")
+        }
     }
 
-    pub fn get_label_childs(label_id: &str) -> Result<Vec<Label>, String> {
-        let mut query: QueryBuilder<Vec<Label>> = QueryBuilder::default();
+    // Backslash before anything that isn't a letter, digit or underscore
+    pub fn escape(value: &str) -> String {
+        let mut escaped = String::with_capacity(value.len());
+
+        for c in value.chars() {
+            if !c.is_alphanumeric() && c != '_' {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
 
-        let base_parent = String::from(label_id);
-        let base_parent_field = String::from("parent");
+        escaped
+    }
+}
 
-        query
-            .index("label-parent-search".to_string())
-            .arg(base_parent_field, base_parent);
+pub mod indexes {
+    use crate::errors::ClassificatorError;
+    use log::{error, info, warn};
+    use redis::{Connection, RedisResult, Value};
 
-        let mut client = create_client().unwrap();
-        let res = query.search(&client);
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FieldKind {
+        Text,
+        Tag,
+        Numeric,
+    }
 
-        if res.is_err() {
-            return Err(res.unwrap_err().to_string());
+    impl FieldKind {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                FieldKind::Text => "TEXT",
+                FieldKind::Tag => "TAG",
+                FieldKind::Numeric => "NUMERIC",
+            }
         }
+    }
 
-        Ok(res.unwrap())
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IndexField {
+        pub name: &'static str,
+        pub kind: FieldKind,
+        pub nostem: bool,
+        pub sortable: bool,
     }
 
-    pub fn get_request_labels(request_id: &str) -> Result<Vec<Label>, String>{
-        let mut client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
-        let mut list: Vec<Label> = vec![];
+    impl IndexField {
+        const fn new(name: &'static str, kind: FieldKind) -> Self {
+            IndexField {
+                name,
+                kind,
+                nostem: false,
+                sortable: false,
+            }
+        }
+
+        pub const fn text(name: &'static str) -> Self {
+            IndexField::new(name, FieldKind::Text)
+        }
 
+        pub const fn tag(name: &'static str) -> Self {
+            IndexField::new(name, FieldKind::Tag)
+        }
 
-        let key = format!("part-request:{request_id}:labels");
+        pub const fn numeric(name: &'static str) -> Self {
+            IndexField::new(name, FieldKind::Numeric)
+        }
 
-        debug!("Gettings labels for {key}");
+        pub const fn nostem(mut self) -> Self {
+            self.nostem = true;
+            self
+        }
 
-        let res: RedisResult<Vec<String>> = con.smembers(key);
-        if res.is_err() {
-            let err = format!("Error getting request labels: {}", res.unwrap_err());
-            error!("{}", err);
-            return Err(err)
+        pub const fn sortable(mut self) -> Self {
+            self.sortable = true;
+            self
         }
+    }
 
-        debug!("Found labels ids: {:?}", res.as_ref().unwrap());
+    // Index over the hashes which keys start with prefix, filter is a RediSearch expression
+    // documents must match to be indexed
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IndexSchema {
+        pub name: &'static str,
+        pub prefix: &'static str,
+        pub filter: Option<&'static str>,
+        pub fields: &'static [IndexField],
+    }
+
+    // Labels, queried by parent
+    pub const LABEL_INDEX: IndexSchema = IndexSchema {
+        name: "label-parent-search",
+        prefix: "part-label:",
+        filter: None,
+        fields: &[
+            IndexField::text("name"),
+            IndexField::text("parent").nostem(),
+        ],
+    };
+
+    // Request hashes, sub-resources share the prefix but have no status. Make, model, year and
+    // requestor are copied from the sub-resources by part_register, the assigned label ids by
+    // the classification scripts
+    pub const REQUEST_INDEX: IndexSchema = IndexSchema {
+        name: "request-search",
+        prefix: "part-request:",
+        filter: Some("exists(@classified)"),
+        fields: &[
+            IndexField::text("classified").nostem(),
+            IndexField::tag("origin"),
+            IndexField::tag("make"),
+            IndexField::tag("model"),
+            IndexField::tag("year"),
+            IndexField::tag("requestor"),
+            IndexField::tag("labels"),
+            IndexField::numeric("timestamp").sortable(),
+        ],
+    };
+
+    // Descriptions, VIN, make and model of every request, stored on `part-request-text:{id}` by
+    // part_register when the details and vehicle are set
+    pub const REQUEST_TEXT_INDEX: IndexSchema = IndexSchema {
+        name: "request-text-search",
+        prefix: "part-request-text:",
+        filter: None,
+        fields: &[
+            IndexField::text("description"),
+            IndexField::text("vin").nostem(),
+            IndexField::text("make").nostem(),
+            IndexField::text("model").nostem(),
+        ],
+    };
+
+    pub const SCHEMAS: [IndexSchema; 3] = [LABEL_INDEX, REQUEST_INDEX, REQUEST_TEXT_INDEX];
+
+    // Steps read by WhatsappSource. The whatsapp workflow owns this index, it's only required to
+    // exist and never created or rebuilt here
+    pub const TRACKER_INDEX: &str = "trackerSteps";
+
+    impl IndexSchema {
+        // FT.CREATE arguments following the index name
+        pub fn create_args(&self) -> Vec<String> {
+            let mut args: Vec<String> = ["ON", "HASH", "PREFIX", "1", self.prefix]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect();
+
+            if let Some(filter) = self.filter {
+                args.push(String::from("FILTER"));
+                args.push(filter.to_string());
+            }
 
-        for label in res.unwrap(){
-            list.push(Label::get(&label).expect("Failed to get label: "))
+            args.push(String::from("SCHEMA"));
+            for field in self.fields {
+                args.push(field.name.to_string());
+                args.push(field.kind.as_str().to_string());
+                if field.nostem {
+                    args.push(String::from("NOSTEM"));
+                }
+                if field.sortable {
+                    args.push(String::from("SORTABLE"));
+                }
+            }
+
+            args
         }
 
-        Ok(list)
+        // Differences with the index redis has, empty when it's up to date
+        pub fn differences(&self, info: &IndexInfo) -> Vec<String> {
+            let mut differences = vec![];
+
+            if info.prefixes != [self.prefix] {
+                differences.push(format!(
+                    "prefixes {:?} instead of [\"{}\"]",
+                    info.prefixes, self.prefix
+                ));
+            }
+
+            if info.filter.as_deref() != self.filter {
+                differences.push(format!(
+                    "filter {:?} instead of {:?}",
+                    info.filter, self.filter
+                ));
+            }
+
+            for field in self.fields {
+                let existing = info.fields.iter().find(|f| f.name == field.name);
+
+                match existing {
+                    None => differences.push(format!("field '{}' missing", field.name)),
+                    Some(existing) => {
+                        let has_flag = |flag: &str| existing.flags.iter().any(|f| f == flag);
+
+                        if !existing.kind.eq_ignore_ascii_case(field.kind.as_str())
+                            || has_flag("NOSTEM") != field.nostem
+                            || has_flag("SORTABLE") != field.sortable
+                        {
+                            differences.push(format!(
+                                "field '{}' is {} {:?}",
+                                field.name, existing.kind, existing.flags
+                            ));
+                        }
+                    }
+                }
+            }
+
+            for existing in &info.fields {
+                if !self.fields.iter().any(|f| f.name == existing.name) {
+                    differences.push(format!("field '{}' not declared", existing.name));
+                }
+            }
+
+            differences
+        }
     }
-    pub fn get_label(id: &str) -> Result<Label, String> {
-        let mut client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
 
-        let key = format!("part-label:{id}");
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct FieldInfo {
+        pub name: String,
+        pub kind: String,
+        pub flags: Vec<String>,
+    }
 
-        let res: RedisResult<Label> = con.hgetall(&key);
+    // Definition of an existing index as reported by FT.INFO
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct IndexInfo {
+        pub prefixes: Vec<String>,
+        pub filter: Option<String>,
+        pub fields: Vec<FieldInfo>,
+    }
 
-        if res.is_err() {
-            let err = format!(
-                "err getting label {}: {}",
-                &key,
-                res.as_ref().unwrap_err().to_string()
+    impl IndexInfo {
+        // FT.INFO replies are lists of name/value pairs. Attributes are listed under `attributes`,
+        // or `fields` before RediSearch 2.2
+        pub fn parse(reply: &Value) -> IndexInfo {
+            let mut info = IndexInfo::default();
+            let items = bulk(reply);
+
+            for pair in items.chunks(2) {
+                let (name, value) = match pair {
+                    [name, value] => (text(name).unwrap_or_default(), value),
+                    _ => continue,
+                };
+
+                match name.as_str() {
+                    "index_definition" => {
+                        let definition = bulk(value);
+                        for pair in definition.chunks(2) {
+                            if let [name, value] = pair {
+                                match text(name).as_deref() {
+                                    Some("prefixes") => {
+                                        info.prefixes =
+                                            bulk(value).iter().filter_map(text).collect();
+                                    }
+                                    Some("filter") => info.filter = text(value),
+                                    _ => {}
+                                }
+                            }
+                        }
+                    }
+                    "attributes" | "fields" => {
+                        info.fields = bulk(value).iter().map(FieldInfo::parse).collect();
+                    }
+                    _ => {}
+                }
+            }
+
+            info
+        }
+    }
+
+    impl FieldInfo {
+        // [identifier, x, attribute, x, type, TEXT, WEIGHT, 1, NOSTEM...], or [x, type, TEXT...]
+        // before RediSearch 2.2
+        fn parse(value: &Value) -> FieldInfo {
+            let words: Vec<String> = bulk(value).iter().filter_map(text).collect();
+            let after = |key: &str| {
+                let position = words.iter().position(|word| word == key)?;
+                words.get(position + 1).cloned()
+            };
+
+            let name = after("attribute")
+                .or_else(|| after("identifier"))
+                .or_else(|| words.first().cloned())
+                .unwrap_or_default();
+            let flags = ["NOSTEM", "SORTABLE"]
+                .iter()
+                .filter(|flag| words.iter().any(|word| word == *flag))
+                .map(|flag| flag.to_string())
+                .collect();
+
+            FieldInfo {
+                name,
+                kind: after("type").unwrap_or_default(),
+                flags,
+            }
+        }
+    }
+
+    fn bulk(value: &Value) -> &[Value] {
+        match value {
+            Value::Bulk(items) => items,
+            _ => &[],
+        }
+    }
+
+    fn text(value: &Value) -> Option<String> {
+        match value {
+            Value::Data(data) => String::from_utf8(data.clone()).ok(),
+            Value::Status(status) => Some(status.clone()),
+            _ => None,
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum IndexState {
+        Missing,
+        Current,
+        Outdated(Vec<String>),
+    }
+
+    // FT.INFO reply of the index, None if it doesn't exist
+    fn index_info(con: &mut Connection, name: &str) -> Result<Option<Value>, ClassificatorError> {
+        let res: RedisResult<Value> = redis::cmd("FT.INFO").arg(name).query(con);
+
+        match res {
+            Ok(reply) => Ok(Some(reply)),
+            Err(err) if err.to_string().to_lowercase().contains("unknown index") => Ok(None),
+            Err(err) => {
+                let error = format!("Error reading index {}: {}", name, err);
+                error!("{}", error);
+                Err(ClassificatorError::Storage(error))
+            }
+        }
+    }
+
+    pub fn index_state(
+        con: &mut Connection,
+        schema: &IndexSchema,
+    ) -> Result<IndexState, ClassificatorError> {
+        let reply = match index_info(con, schema.name)? {
+            Some(reply) => reply,
+            None => return Ok(IndexState::Missing),
+        };
+
+        let differences = schema.differences(&IndexInfo::parse(&reply));
+        if differences.is_empty() {
+            Ok(IndexState::Current)
+        } else {
+            Ok(IndexState::Outdated(differences))
+        }
+    }
+
+    pub fn create_index(
+        con: &mut Connection,
+        schema: &IndexSchema,
+    ) -> Result<(), ClassificatorError> {
+        let res: RedisResult<()> = redis::cmd("FT.CREATE")
+            .arg(schema.name)
+            .arg(schema.create_args())
+            .query(con);
+
+        if let Err(err) = res {
+            let error = format!("Error creating index {}: {}", schema.name, err);
+            error!("{}", error);
+            return Err(ClassificatorError::Storage(error));
+        }
+
+        info!("Index {} created", schema.name);
+        Ok(())
+    }
+
+    // Hashes are kept, only the index is dropped
+    fn drop_index(con: &mut Connection, schema: &IndexSchema) -> Result<(), ClassificatorError> {
+        let res: RedisResult<()> = redis::cmd("FT.DROPINDEX").arg(schema.name).query(con);
+
+        if let Err(err) = res {
+            let error = format!("Error dropping index {}: {}", schema.name, err);
+            error!("{}", error);
+            return Err(ClassificatorError::Storage(error));
+        }
+
+        Ok(())
+    }
+
+    // Creates the missing indexes and returns their names. Fails if an index differs from its
+    // schema, those are only rebuilt by migrate_indexes, or if the tracker index is missing
+    pub fn verify_indexes(con: &mut Connection) -> Result<Vec<&'static str>, ClassificatorError> {
+        if index_info(con, TRACKER_INDEX)?.is_none() {
+            let error = format!(
+                "Index {} is missing, it's created by the whatsapp workflow",
+                TRACKER_INDEX
             );
-            error!("{}",err);
-            return Err(err) 
+            error!("{}", error);
+            return Err(ClassificatorError::Storage(error));
+        }
+
+        let mut created = vec![];
+
+        for schema in &SCHEMAS {
+            match index_state(con, schema)? {
+                IndexState::Current => {}
+                IndexState::Missing => {
+                    create_index(con, schema)?;
+                    created.push(schema.name);
+                }
+                IndexState::Outdated(differences) => {
+                    let error = format!(
+                        "Index {} doesn't match its schema ({}), run the migrate-indexes command",
+                        schema.name,
+                        differences.join(", ")
+                    );
+                    error!("{}", error);
+                    return Err(ClassificatorError::Storage(error));
+                }
+            }
         }
 
-       Ok(res.unwrap()) 
+        Ok(created)
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Migration {
+        Created,
+        Rebuilt,
+        Unchanged,
+    }
+
+    // Creates the missing indexes and rebuilds the outdated ones, or every index when forced.
+    // Redis indexes the existing hashes again in the background
+    pub fn migrate_indexes(
+        con: &mut Connection,
+        force: bool,
+    ) -> Result<Vec<(&'static str, Migration)>, ClassificatorError> {
+        let mut migrations = vec![];
+
+        for schema in &SCHEMAS {
+            let migration = match index_state(con, schema)? {
+                IndexState::Missing => {
+                    create_index(con, schema)?;
+                    Migration::Created
+                }
+                IndexState::Current if !force => Migration::Unchanged,
+                state => {
+                    if let IndexState::Outdated(differences) = state {
+                        warn!("Rebuilding {}: {}", schema.name, differences.join(", "));
+                    }
+                    drop_index(con, schema)?;
+                    create_index(con, schema)?;
+                    Migration::Rebuilt
+                }
+            };
+
+            migrations.push((schema.name, migration));
+        }
+
+        Ok(migrations)
     }
 }
 
 pub mod common {
-    use fizzy_commons::redis::client::create_client;
-    use log::{debug, error};
-    use redis::Commands;
+    use crate::errors::ClassificatorError;
+    use log::error;
     use redis::RedisError;
-    use redis::RedisResult;
     use redis::Value;
+    use redis::{Commands, Connection, FromRedisValue};
 
-    pub fn key_exists(key: &str) -> bool {
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
-
-        let res: RedisResult<Value> = con.exists(key);
+    pub fn key_exists(con: &mut Connection, key: &str) -> Result<bool, ClassificatorError> {
+        let res: Value = con.exists(key)?;
 
-        match res.unwrap() {
-            Value::Int(response_code) => response_code == 1,
+        match res {
+            Value::Int(response_code) => Ok(response_code == 1),
             _ => {
                 error!("Unexpected response type received");
-                panic!("Unexpected response type received")
+                Err(ClassificatorError::Storage(String::from(
+                    "Unexpected response type received",
+                )))
             }
         }
     }
 
-    pub fn get_user_mode(phone_number: &str) -> Result<u16, String> {
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
-
-        let mode: RedisResult<String> = con.hget(format!("selected-mode:{}", phone_number), "mode");
+    // HGETALL parsed into T, None when the hash doesn't exist
+    pub fn get_hash<T: FromRedisValue>(
+        con: &mut Connection,
+        key: &str,
+    ) -> Result<Option<T>, ClassificatorError> {
+        let res: Value = con.hgetall(key)?;
 
-        if mode.is_err() {
-            let is_nil = is_nil(mode.as_ref().unwrap_err());
+        parse_hash(key, &res)
+    }
 
-            if is_nil {
-                error!("Key has no value");
-                return Err("Key has no value".to_string());
-            } else {
-                error!("{}", mode.as_ref().unwrap_err().to_string());
-                return Err(String::from("Other error"));
-            };
+    // HGETALL reply parsed into T, None when the hash doesn't exist
+    pub fn parse_hash<T: FromRedisValue>(
+        key: &str,
+        res: &Value,
+    ) -> Result<Option<T>, ClassificatorError> {
+        if let Value::Bulk(fields) = res {
+            if fields.is_empty() {
+                return Ok(None);
+            }
         }
 
-        let parsed_mode = mode.unwrap().parse::<u16>().unwrap();
-        Ok(parsed_mode)
+        match T::from_redis_value(res) {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(parse_err) => {
+                let err = format!("Error parsing {}: {}", key, parse_err);
+                error!("{}", err);
+                Err(ClassificatorError::Storage(err))
+            }
+        }
     }
 
-    pub fn is_nil(error: &RedisError) -> bool {
-        if error.to_string().contains("response was nil") {
-            true
-        } else {
-            false
+    // Scripts report failed checks as '<CODE> <message>' error replies
+    pub fn script_error(err: RedisError) -> ClassificatorError {
+        let message = err.detail().unwrap_or_default().to_string();
+
+        match err.code() {
+            Some("NOT_FOUND") => ClassificatorError::NotFound(message),
+            Some("CONFLICT") => ClassificatorError::Conflict(message),
+            _ => {
+                error!("Script failed: {}", err);
+                ClassificatorError::Storage(err.to_string())
+            }
         }
     }
 }
 
 pub mod part_register {
-    use crate::structs::part_request::{PartRequest, RequestDetails, Requestor, VehicleData};
-    use fizzy_commons::redis::client::create_client;
+    use crate::errors::ClassificatorError;
+    use crate::redis::common::{get_hash, parse_hash, script_error};
+    use crate::redis::indexes::REQUEST_TEXT_INDEX;
+    use crate::storage::{RequestAggregate, RequestInclude};
+    use crate::structs::classification::Label;
+    use crate::structs::part_request::{
+        PartRequest, RequestDetails, RequestEvent, Requestor, VehicleData,
+    };
     use log::{debug, error};
-    use redis::{Commands, RedisResult, Value};
-    use std::time::{SystemTime, UNIX_EPOCH};
+    use redis::{Commands, Connection, FromRedisValue, Pipeline, RedisResult, Script, Value};
+
+    // Maps an origin reference to the request created for it, so retried notifications don't
+    // create duplicates
+    pub fn reference_key(origin: &str, reference: &str) -> String {
+        format!("part-request-reference:{}:{}", origin, reference)
+    }
+
+    // KEYS: request hash, vehicle hash, details hash, requestor hash, text document, reference
+    // key. ARGV: request id, then for every hash its field count followed by fields and values
+    const CREATE_PART_REQUEST: &str = r#"
+local existing = redis.call('GET', KEYS[6])
+if existing then
+    return redis.error_reply("CONFLICT Part request " .. existing ..
+        " already has this origin reference")
+end
+
+local position = 2
+for i = 1, 5 do
+    local count = tonumber(ARGV[position])
+    if count > 0 then
+        redis.call('HSET', KEYS[i], unpack(ARGV, position + 1, position + count * 2))
+    end
+    position = position + count * 2 + 1
+end
+
+redis.call('SET', KEYS[6], ARGV[1])
+return ARGV[1]
+"#;
+
+    pub fn create_part_request(
+        con: &mut Connection,
+        request: &PartRequest,
+    ) -> Result<(), ClassificatorError> {
+        let key = format!("part-request:{}", request.id);
+        let (index_fields, mut text_fields) = search_fields(request);
+
+        let mut request_fields = request.get_redis_list();
+        request_fields.extend(index_fields);
+        if !text_fields.is_empty() {
+            text_fields.push((String::from("request_id"), request.id.clone()));
+        }
+
+        let hashes = [
+            request_fields,
+            request
+                .vehicle
+                .as_ref()
+                .map(VehicleData::get_redis_fields)
+                .unwrap_or_default(),
+            request
+                .details
+                .as_ref()
+                .map(RequestDetails::get_redis_fields)
+                .unwrap_or_default(),
+            request
+                .requestor
+                .as_ref()
+                .map(Requestor::get_redis_fields)
+                .unwrap_or_default(),
+            text_fields,
+        ];
+
+        let script = Script::new(CREATE_PART_REQUEST);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(&key)
+            .key(format!("{key}:vehicle"))
+            .key(format!("{key}:request-details"))
+            .key(format!("{key}:requestor"))
+            .key(format!("{}{}", REQUEST_TEXT_INDEX.prefix, request.id))
+            .key(reference_key(&request.origin, &request.origin_reference))
+            .arg(&request.id);
+
+        for fields in &hashes {
+            invocation.arg(fields.len());
+            for (field, value) in fields {
+                invocation.arg(field).arg(value);
+            }
+        }
+
+        let res: RedisResult<String> = invocation.invoke(con);
+        res.map_err(script_error)?;
+
+        debug!("Part request {} created", request.id);
+        Ok(())
+    }
+
+    type HashFields = Vec<(String, String)>;
+
+    // Fields of the sub-resources copied to the request hash for request-search, and to the
+    // request text document for request-text-search
+    fn search_fields(request: &PartRequest) -> (HashFields, HashFields) {
+        let mut index_fields = vec![];
+        let mut text_fields = vec![];
+
+        if let Some(vehicle) = &request.vehicle {
+            index_fields.extend(vehicle.get_index_fields());
+            text_fields.extend(vehicle.get_text_fields());
+        }
+        if let Some(requestor) = &request.requestor {
+            index_fields.push((String::from("requestor"), requestor.user_id.clone()));
+        }
+        if let Some(details) = &request.details {
+            text_fields.extend(details.get_text_fields());
+        }
 
-    pub fn append_label(part_request_id: &str, label_id: &str) -> Result<Value, String> {
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
+        (index_fields, text_fields)
+    }
 
-        // Key
-        let key = format!("part-request:{}:labels", part_request_id);
+    pub fn find_request_by_reference(
+        con: &mut Connection,
+        origin: &str,
+        reference: &str,
+    ) -> Result<Option<String>, ClassificatorError> {
+        let request_id: Option<String> = con.get(reference_key(origin, reference))?;
+        Ok(request_id)
+    }
 
-        let res: RedisResult<Value> = con.sadd(key, label_id);
+    // Copies the fields to the request text document, see indexes::REQUEST_TEXT_INDEX
+    fn set_text_fields(
+        pipe: &mut Pipeline,
+        part_request_id: &str,
+        mut fields: Vec<(String, String)>,
+    ) {
+        if fields.is_empty() {
+            return;
+        }
 
-        if res.is_err() {
-            // TODO
+        fields.push((String::from("request_id"), part_request_id.to_string()));
+        let key = format!("{}{}", REQUEST_TEXT_INDEX.prefix, part_request_id);
+        pipe.hset_multiple(key, &fields).ignore();
+    }
+
+    // Copies the fields searched by request-search and request-text-search from the
+    // sub-resources and labels of requests stored before they were indexed, and adds their
+    // missing reference keys. Requests that cant be decoded are logged and skipped. Returns the
+    // requests that were actually changed
+    pub fn backfill_request_fields(con: &mut Connection) -> Result<usize, ClassificatorError> {
+        let keys: Vec<String> = con.scan_match("part-request:*")?.collect();
+        let ids = keys
+            .iter()
+            .filter_map(|key| key.strip_prefix("part-request:"))
+            .filter(|id| !id.contains(':'));
+
+        let include = RequestInclude {
+            vehicle: true,
+            requestor: true,
+            details: true,
+            ..RequestInclude::default()
+        };
+        let mut updated = 0;
+
+        for id in ids {
+            let request = match load_request(con, id, &include) {
+                Ok(loaded) => loaded.request,
+                Err(err) => {
+                    error!("Skipping part request {} on backfill: {}", id, err);
+                    continue;
+                }
+            };
+            let (mut index_fields, text_fields) = search_fields(&request);
+
+            let mut labels: Vec<String> = con.smembers(format!("part-request:{id}:labels"))?;
+            if !labels.is_empty() {
+                labels.sort();
+                index_fields.push((String::from("labels"), labels.join(",")));
+            }
+
+            let request_key = format!("part-request:{id}");
+            let index_fields = changed_fields(con, &request_key, index_fields)?;
+            let text_key = format!("{}{}", REQUEST_TEXT_INDEX.prefix, id);
+            let text_fields = changed_fields(con, &text_key, text_fields)?;
+            let reference = reference_key(&request.origin, &request.origin_reference);
+            let has_reference: bool = con.exists(&reference)?;
+
+            if index_fields.is_empty() && text_fields.is_empty() && has_reference {
+                continue;
+            }
+
+            let mut pipe = redis::pipe();
+            pipe.atomic().set_nx(reference, id).ignore();
+            if !index_fields.is_empty() {
+                pipe.hset_multiple(request_key, &index_fields).ignore();
+            }
+            set_text_fields(&mut pipe, id, text_fields);
+
+            let _: () = pipe.query(con)?;
+            updated += 1;
+        }
+
+        Ok(updated)
+    }
+
+    // Fields which stored value differs from the given one
+    fn changed_fields(
+        con: &mut Connection,
+        key: &str,
+        fields: HashFields,
+    ) -> Result<HashFields, ClassificatorError> {
+        if fields.is_empty() {
+            return Ok(fields);
         }
 
-        Ok(res.unwrap())
+        let names: Vec<&String> = fields.iter().map(|(name, _)| name).collect();
+        let stored: Vec<Option<String>> = redis::cmd("HMGET").arg(key).arg(names).query(con)?;
+
+        Ok(fields
+            .into_iter()
+            .zip(stored)
+            .filter(|((_, value), stored)| stored.as_ref() != Some(value))
+            .map(|(field, _)| field)
+            .collect())
     }
 
-    pub fn create_part_request(origin: &str, reference: &str) -> Result<PartRequest, String> {
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
+    // KEYS: request labels set. Hashes of the assigned labels, missing ones are skipped
+    const ASSIGNED_LABELS: &str = r#"
+local labels = {}
+for _, id in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    local label = redis.call('HGETALL', 'part-label:' .. id)
+    if #label > 0 then
+        table.insert(labels, label)
+    end
+end
+return labels
+"#;
+
+    pub fn load_request(
+        con: &mut Connection,
+        request_id: &str,
+        include: &RequestInclude,
+    ) -> Result<RequestAggregate, ClassificatorError> {
+        let key = format!("part-request:{request_id}");
+        let vehicle_key = format!("{key}:vehicle");
+        let requestor_key = format!("{key}:requestor");
+        let details_key = format!("{key}:request-details");
+
+        let mut pipe = redis::pipe();
+        pipe.hgetall(&key);
+        if include.vehicle {
+            pipe.hgetall(&vehicle_key);
+        }
+        if include.requestor {
+            pipe.hgetall(&requestor_key);
+        }
+        if include.details {
+            pipe.hgetall(&details_key);
+        }
+        if include.labels {
+            pipe.cmd("EVAL")
+                .arg(ASSIGNED_LABELS)
+                .arg(1)
+                .arg(format!("{key}:labels"));
+        }
+        if include.history {
+            pipe.xrange_all(format!("{key}:events"));
+        }
 
-        // Request ID
-        let uuid = uuid::Uuid::new_v4().to_string();
-        let request_id = format!("part-request:{}", uuid);
+        let replies: Vec<Value> = pipe.query(con)?;
+        let mut replies = replies.iter();
+        // Replies come in the same order the commands were queued
+        let mut next = || replies.next().unwrap_or(&Value::Nil);
 
-        // Timestamp
-        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
-            Ok(n) => n.as_millis().to_string(),
-            Err(_) => panic!("SystemTime before UNIX EPOCH!"),
+        let mut request: PartRequest = match parse_hash(&key, next())? {
+            Some(request) => request,
+            None => {
+                let err = format!("Part request '{}' doesnt exist", request_id);
+                return Err(ClassificatorError::NotFound(err));
+            }
         };
 
-        let part_request =
-            PartRequest::new(&uuid, origin, reference, timestamp.as_str(), "PENDING");
-        let redis_fields = part_request.get_redis_list();
+        if include.vehicle {
+            request.vehicle = parse_hash(&vehicle_key, next())?;
+        }
+        if include.requestor {
+            request.requestor = parse_hash(&requestor_key, next())?;
+        }
+        if include.details {
+            request.details = parse_hash(&details_key, next())?;
+        }
 
-        let res: RedisResult<Value> = con.hset_multiple(request_id, &redis_fields);
+        let mut labels: Vec<Label> = vec![];
+        if include.labels {
+            let res: RedisResult<Vec<Label>> = FromRedisValue::from_redis_value(next());
+            labels = res.map_err(|err| {
+                ClassificatorError::Storage(format!("Error parsing {key}:labels: {err}"))
+            })?;
+        }
+        if include.history {
+            let res: RedisResult<Vec<RequestEvent>> = FromRedisValue::from_redis_value(next());
+            let history = res.map_err(|err| {
+                ClassificatorError::Storage(format!("Error parsing {key}:events: {err}"))
+            })?;
+            request.history = Some(history);
+        }
+
+        Ok(RequestAggregate { request, labels })
+    }
 
-        if res.is_err() {
-            error!("{}", res.as_ref().unwrap_err());
-            return Err(format!("{}", res.as_ref().unwrap_err().to_string()));
+    pub fn get_request_by_id(
+        con: &mut Connection,
+        request_id: &str,
+    ) -> Result<PartRequest, ClassificatorError> {
+        let key = format!("part-request:{request_id}");
+
+        match get_hash(con, &key)? {
+            Some(request) => Ok(request),
+            None => Err(ClassificatorError::NotFound(format!(
+                "Part request '{}' doesnt exist",
+                request_id
+            ))),
         }
+    }
+}
 
-        Ok(part_request)
+pub mod taxonomy {
+    use crate::errors::ClassificatorError;
+    use crate::redis::classification::{get_all_labels, get_label_subtree, requests_with_labels};
+    use crate::redis::common::{key_exists, script_error};
+    use crate::storage::MAX_LABEL_DEPTH;
+    use crate::structs::classification::Label;
+    use log::debug;
+    use redis::{Connection, RedisResult, Script};
+    use std::collections::HashSet;
+
+    // ARGV: label id (empty to take the next one from part-label-sequence), name, parent
+    const CREATE_LABEL: &str = r#"
+local id, name, parent = ARGV[1], ARGV[2], ARGV[3]
+
+if parent ~= '0' and redis.call('EXISTS', 'part-label:' .. parent) == 0 then
+    return redis.error_reply("NOT_FOUND Parent label '" .. parent .. "' doesnt exist")
+end
+
+if id == '' then
+    repeat
+        id = tostring(redis.call('INCR', 'part-label-sequence'))
+    until redis.call('EXISTS', 'part-label:' .. id) == 0
+elseif redis.call('EXISTS', 'part-label:' .. id) == 1 then
+    return redis.error_reply("CONFLICT Label '" .. id .. "' already exists")
+end
+
+redis.call('HSET', 'part-label:' .. id, 'id', id, 'name', name, 'parent', parent)
+return redis.call('HGETALL', 'part-label:' .. id)
+"#;
+
+    // ARGV: label id, name and parent (empty to keep the current one), max depth
+    const UPDATE_LABEL: &str = r#"
+local id, name, parent, max_depth = ARGV[1], ARGV[2], ARGV[3], tonumber(ARGV[4])
+local key = 'part-label:' .. id
+
+if redis.call('EXISTS', key) == 0 then
+    return redis.error_reply("NOT_FOUND Label '" .. id .. "' doesnt exist")
+end
+
+local current, depth = parent, 0
+while current and current ~= '' and current ~= '0' do
+    if current == id then
+        return redis.error_reply("CONFLICT Label '" .. id ..
+            "' cannot be moved under itself or its descendants")
+    end
+
+    depth = depth + 1
+    if depth > max_depth then
+        return redis.error_reply("CYCLE Label '" .. parent .. "' ancestors contain a cycle")
+    end
+
+    if redis.call('EXISTS', 'part-label:' .. current) == 0 then
+        return redis.error_reply("NOT_FOUND Parent label '" .. current .. "' doesnt exist")
+    end
+
+    current = redis.call('HGET', 'part-label:' .. current, 'parent')
+end
+
+if name ~= '' then
+    redis.call('HSET', key, 'name', name)
+end
+
+if parent ~= '' then
+    redis.call('HSET', key, 'parent', parent)
+end
+
+return redis.call('HGETALL', key)
+"#;
+
+    // KEYS: hashes of the label and every label below it, then the hash and labels set of every
+    // request they're assigned to. ARGV: ids of the labels, the deleted one first.
+    // The subtree and requests are looked up before, so labels or assignments added meanwhile
+    // aren't seen
+    const DELETE_LABEL: &str = r#"
+local count = #ARGV
+
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return redis.error_reply("NOT_FOUND Label '" .. ARGV[1] .. "' doesnt exist")
+end
+
+for i = count + 1, #KEYS, 2 do
+    for _, id in ipairs(ARGV) do
+        redis.call('SREM', KEYS[i + 1], id)
+    end
+
+    if redis.call('EXISTS', KEYS[i]) == 1 then
+        local ids = redis.call('SMEMBERS', KEYS[i + 1])
+        table.sort(ids)
+        redis.call('HSET', KEYS[i], 'labels', table.concat(ids, ','))
+    end
+end
+
+for i = 1, count do
+    redis.call('DEL', KEYS[i])
+end
+
+return ARGV
+"#;
+
+    // KEYS: hashes of the imported labels, then of the deleted ones. ARGV: id, name and parent
+    // of every imported label. Returns the count of labels deleted
+    const REPLACE_LABELS: &str = r#"
+local count = #ARGV / 3
+
+for i = 1, count do
+    local position = (i - 1) * 3
+    redis.call('HSET', KEYS[i], 'id', ARGV[position + 1], 'name', ARGV[position + 2],
+        'parent', ARGV[position + 3])
+end
+
+for i = count + 1, #KEYS do
+    redis.call('DEL', KEYS[i])
+end
+
+return #KEYS - count
+"#;
+
+    pub fn create_label(
+        con: &mut Connection,
+        id: Option<&str>,
+        name: &str,
+        parent: &str,
+    ) -> Result<Label, ClassificatorError> {
+        let res: RedisResult<Label> = Script::new(CREATE_LABEL)
+            .arg(id.unwrap_or_default())
+            .arg(name)
+            .arg(parent)
+            .invoke(con);
+
+        let label = res.map_err(script_error)?;
+        debug!("Created label {label:?}");
+
+        Ok(label)
     }
 
-    pub fn set_request_vehicle_information(
-        part_request_id: &str,
-        vehicle_data: &VehicleData,
-    ) -> Result<(), String> {
-        // Get Client
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
+    pub fn update_label(
+        con: &mut Connection,
+        id: &str,
+        name: Option<&str>,
+        parent: Option<&str>,
+    ) -> Result<Label, ClassificatorError> {
+        let res: RedisResult<Label> = Script::new(UPDATE_LABEL)
+            .arg(id)
+            .arg(name.unwrap_or_default())
+            .arg(parent.unwrap_or_default())
+            .arg(MAX_LABEL_DEPTH)
+            .invoke(con);
+
+        let label = res.map_err(script_error)?;
+        debug!("Updated label {label:?}");
+
+        Ok(label)
+    }
 
-        // get redis field tuples from struct
-        let redis_fields = vehicle_data.get_redis_fields();
+    pub fn delete_label(
+        con: &mut Connection,
+        id: &str,
+        cascade: bool,
+    ) -> Result<Vec<String>, ClassificatorError> {
+        if !key_exists(con, &format!("part-label:{id}"))? {
+            let err = format!("Label '{}' doesnt exist", id);
+            return Err(ClassificatorError::NotFound(err));
+        }
 
-        // Key
-        let key = format!("part-request:{}:vehicle", part_request_id);
+        let subtree = get_label_subtree(con, id)?;
+        if !cascade && subtree.len() > 1 {
+            let err = format!("Label '{id}' has child labels, use cascade to delete them");
+            return Err(ClassificatorError::Conflict(err));
+        }
 
-        let res: RedisResult<Value> = con.hset_multiple(&key, &redis_fields);
+        let requests = requests_with_labels(con, &subtree)?;
+        if !cascade && !requests.is_empty() {
+            let err = format!(
+                "Label '{}' is assigned to {} requests, use cascade to unassign it",
+                id,
+                requests.len()
+            );
+            return Err(ClassificatorError::Conflict(err));
+        }
 
-        if res.is_err() {
-            return Err(res.as_ref().unwrap_err().to_string());
+        let script = Script::new(DELETE_LABEL);
+        let mut invocation = script.prepare_invoke();
+        for label_id in &subtree {
+            invocation
+                .key(format!("part-label:{label_id}"))
+                .arg(label_id);
+        }
+        for (request_id, _) in &requests {
+            invocation
+                .key(format!("part-request:{request_id}"))
+                .key(format!("part-request:{request_id}:labels"));
         }
 
-        debug!("Vehicle data added succesfully {}", &key);
-        Ok(())
+        let res: RedisResult<Vec<String>> = invocation.invoke(con);
+
+        let mut deleted = res.map_err(script_error)?;
+        deleted.sort();
+        debug!("Deleted labels {deleted:?}");
+
+        Ok(deleted)
     }
 
-    pub fn set_request_details(
-        part_request_id: &str,
-        details: &RequestDetails,
-    ) -> Result<(), String> {
-        // Get Client
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
+    pub fn replace_labels(
+        con: &mut Connection,
+        labels: &[Label],
+    ) -> Result<Vec<String>, ClassificatorError> {
+        let imported: HashSet<&str> = labels.iter().map(|label| label.id.as_str()).collect();
+        let mut deleted: Vec<String> = get_all_labels(con)?
+            .into_iter()
+            .map(|label| label.id)
+            .filter(|id| !imported.contains(id.as_str()))
+            .collect();
+        deleted.sort();
+
+        // Labels assigned to requests are never deleted by an import
+        let mut in_use: Vec<String> = requests_with_labels(con, &deleted)?
+            .into_iter()
+            .flat_map(|(_, labels)| labels)
+            .filter(|id| deleted.binary_search(id).is_ok())
+            .collect();
+        in_use.sort();
+        in_use.dedup();
+
+        if !in_use.is_empty() {
+            let err = format!(
+                "Labels assigned to requests cant be deleted: {}",
+                in_use.join(", ")
+            );
+            return Err(ClassificatorError::Conflict(err));
+        }
 
-        // get redis field tuples from struct
-        let redis_fields = details.get_redis_fields();
+        let script = Script::new(REPLACE_LABELS);
+        let mut invocation = script.prepare_invoke();
+        for label in labels {
+            invocation
+                .key(format!("part-label:{}", label.id))
+                .arg(&label.id)
+                .arg(&label.name)
+                .arg(&label.parent);
+        }
+        for id in &deleted {
+            invocation.key(format!("part-label:{id}"));
+        }
 
-        // Key
-        let key = format!("part-request:{}:request-details", part_request_id);
+        let res: RedisResult<usize> = invocation.invoke(con);
+        res.map_err(script_error)?;
+        debug!("Replaced labels, deleted {deleted:?}");
 
-        let res: RedisResult<Value> = con.hset_multiple(&key, &redis_fields);
+        Ok(deleted)
+    }
+}
+
+pub mod claims {
+    use crate::errors::ClassificatorError;
+    use crate::redis::classification::get_pending_classification_requests;
+    use crate::redis::common::script_error;
+    use crate::structs::part_request::{Claim, ClaimedRequest};
+    use log::debug;
+    use redis::{Connection, RedisResult, Script};
+
+    // KEYS: request hash, request claim. ARGV: owner, ttl in ms.
+    // Claims the request only if it's still pending and nobody holds it
+    const CLAIM_REQUEST: &str = r#"
+if redis.call('HGET', KEYS[1], 'classified') ~= 'PENDING' then
+    return 0
+end
+
+if redis.call('SET', KEYS[2], ARGV[1], 'NX', 'PX', ARGV[2]) then
+    return 1
+end
+return 0
+"#;
+
+    // KEYS: request claim. ARGV: request id, owner, ttl in ms (empty to release the claim)
+    const UPDATE_CLAIM: &str = r#"
+local request_id, owner, ttl = ARGV[1], ARGV[2], ARGV[3]
+
+local current = redis.call('GET', KEYS[1])
+if not current then
+    return redis.error_reply("NOT_FOUND Part request '" .. request_id .. "' is not claimed")
+end
+
+if current ~= owner then
+    return redis.error_reply("CONFLICT Part request '" .. request_id .. "' is claimed by " ..
+        current)
+end
+
+if ttl == '' then
+    redis.call('DEL', KEYS[1])
+else
+    redis.call('PEXPIRE', KEYS[1], ttl)
+end
+return 1
+"#;
+
+    fn claim_key(request_id: &str) -> String {
+        format!("part-request:{request_id}:claim")
+    }
+
+    // Tries the pending requests oldest first until one is claimed
+    pub fn claim_next_request(
+        con: &mut Connection,
+        owner: &str,
+        ttl_ms: u64,
+    ) -> Result<Option<ClaimedRequest>, ClassificatorError> {
+        let mut pending = get_pending_classification_requests(con)?;
+        pending.sort_by_key(|request| request.timestamp.parse::<u128>().unwrap_or_default());
+
+        // Skip the ones already claimed without running the script on them
+        let keys: Vec<String> = pending.iter().map(|r| claim_key(&r.id)).collect();
+        let owners: Vec<Option<String>> = match keys.is_empty() {
+            true => vec![],
+            false => redis::cmd("MGET").arg(&keys).query(con)?,
+        };
 
-        if res.is_err() {
-            return Err(res.as_ref().unwrap_err().to_string());
+        for (request, current) in pending.into_iter().zip(owners) {
+            if current.is_some() {
+                continue;
+            }
+
+            let res: RedisResult<bool> = Script::new(CLAIM_REQUEST)
+                .key(format!("part-request:{}", request.id))
+                .key(claim_key(&request.id))
+                .arg(owner)
+                .arg(ttl_ms)
+                .invoke(con);
+
+            if res.map_err(script_error)? {
+                debug!("Request {} claimed by {owner}", request.id);
+                let claim = Claim::new(&request.id, owner, ttl_ms);
+                return Ok(Some(ClaimedRequest { claim, request }));
+            }
         }
 
-        debug!("Request details added succesfully {}", &key);
+        Ok(None)
+    }
+
+    pub fn renew_claim(
+        con: &mut Connection,
+        request_id: &str,
+        owner: &str,
+        ttl_ms: u64,
+    ) -> Result<Claim, ClassificatorError> {
+        let res: RedisResult<i64> = Script::new(UPDATE_CLAIM)
+            .key(claim_key(request_id))
+            .arg(request_id)
+            .arg(owner)
+            .arg(ttl_ms)
+            .invoke(con);
+        res.map_err(script_error)?;
+
+        Ok(Claim::new(request_id, owner, ttl_ms))
+    }
+
+    pub fn release_claim(
+        con: &mut Connection,
+        request_id: &str,
+        owner: &str,
+    ) -> Result<(), ClassificatorError> {
+        let res: RedisResult<i64> = Script::new(UPDATE_CLAIM)
+            .key(claim_key(request_id))
+            .arg(request_id)
+            .arg(owner)
+            .arg("")
+            .invoke(con);
+        res.map_err(script_error)?;
+
         Ok(())
     }
 
-    pub fn set_request_requestor(
-        part_request_id: &str,
-        requestor: &Requestor,
-    ) -> Result<(), String> {
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
+    pub fn get_claim(
+        con: &mut Connection,
+        request_id: &str,
+    ) -> Result<Option<Claim>, ClassificatorError> {
+        let (owner, ttl): (Option<String>, i64) = redis::pipe()
+            .get(claim_key(request_id))
+            .pttl(claim_key(request_id))
+            .query(con)?;
+
+        // Negative ttl means the claim expired between both commands
+        match owner {
+            Some(owner) if ttl > 0 => Ok(Some(Claim::new(request_id, &owner, ttl as u64))),
+            _ => Ok(None),
+        }
+    }
+}
 
-        let redis_fields = requestor.get_redis_fields();
+pub mod pool {
+    use crate::errors::ClassificatorError;
+    use log::info;
+    use redis::{Client, Connection};
+    use std::env;
+    use std::time::Duration;
+
+    pub type RedisPool = r2d2::Pool<Client>;
+
+    // Pool settings, read from the REDIS_POOL_* environment variables
+    #[derive(Debug, Clone)]
+    pub struct PoolConfig {
+        pub max_size: u32,
+        pub min_idle: Option<u32>,
+        pub connection_timeout: Duration,
+        pub idle_timeout: Option<Duration>,
+        pub command_timeout: Option<Duration>,
+    }
+
+    impl Default for PoolConfig {
+        fn default() -> Self {
+            PoolConfig {
+                max_size: 16,
+                min_idle: Some(1),
+                connection_timeout: Duration::from_millis(5000),
+                idle_timeout: Some(Duration::from_secs(600)),
+                command_timeout: Some(Duration::from_millis(5000)),
+            }
+        }
+    }
+
+    impl PoolConfig {
+        pub fn from_env() -> Self {
+            let default = PoolConfig::default();
+
+            PoolConfig {
+                max_size: env_var("REDIS_POOL_MAX_SIZE").unwrap_or(default.max_size),
+                min_idle: env_var("REDIS_POOL_MIN_IDLE").or(default.min_idle),
+                connection_timeout: env_var("REDIS_POOL_CONNECTION_TIMEOUT_MS")
+                    .map(Duration::from_millis)
+                    .unwrap_or(default.connection_timeout),
+                idle_timeout: env_var("REDIS_POOL_IDLE_TIMEOUT_SECS")
+                    .map(Duration::from_secs)
+                    .or(default.idle_timeout),
+                command_timeout: env_var("REDIS_COMMAND_TIMEOUT_MS")
+                    .map(Duration::from_millis)
+                    .or(default.command_timeout),
+            }
+        }
+    }
 
-        // Key
-        let key = format!("part-request:{}:requestor", part_request_id);
+    fn env_var<T: std::str::FromStr>(name: &str) -> Option<T> {
+        env::var(name)
+            .ok()
+            .and_then(|value| value.parse::<T>().ok())
+    }
 
-        let res: RedisResult<Value> = con.hset_multiple(&key, &redis_fields);
+    // Applies the command timeout to every connection handed out by the pool
+    #[derive(Debug)]
+    struct CommandTimeout(Option<Duration>);
 
-        if res.is_err() {
-            error!(
-                "Error adding requestor to part request: {}",
-                res.as_ref().unwrap_err().to_string()
-            );
-            return Err(res.as_ref().unwrap_err().to_string());
+    impl r2d2::CustomizeConnection<Connection, redis::RedisError> for CommandTimeout {
+        fn on_acquire(&self, con: &mut Connection) -> Result<(), redis::RedisError> {
+            con.set_read_timeout(self.0)?;
+            con.set_write_timeout(self.0)
         }
+    }
 
-        debug!("Requestor added succesfully {}", &key);
-        Ok(())
+    pub fn create_pool(
+        client: Client,
+        config: &PoolConfig,
+    ) -> Result<RedisPool, ClassificatorError> {
+        info!("Creating redis connection pool: {config:?}");
+
+        r2d2::Pool::builder()
+            .max_size(config.max_size)
+            .min_idle(config.min_idle)
+            .connection_timeout(config.connection_timeout)
+            .idle_timeout(config.idle_timeout)
+            .connection_customizer(Box::new(CommandTimeout(config.command_timeout)))
+            .build(client)
+            .map_err(|err| {
+                ClassificatorError::Storage(format!(
+                    "Error creating redis connection pool: {}",
+                    err
+                ))
+            })
+    }
+}
+
+pub mod store {
+    use super::indexes::{self, Migration};
+    use super::pool::RedisPool;
+    use super::{claims, classification, common, part_register, taxonomy};
+    use crate::errors::ClassificatorError;
+    use crate::storage::{
+        LabelStore, RequestAggregate, RequestFilter, RequestInclude, RequestStore,
+    };
+    use crate::structs::classification::Label;
+    use crate::structs::part_request::{
+        Claim, ClaimedRequest, PartRequest, RequestEvent, RequestMatches, RequestPage,
+        RequestStatus,
+    };
+    use log::error;
+    use r2d2::PooledConnection;
+    use redis::Client;
+
+    // Redis Stack backed store, delegates to the classification and part_register functions
+    // using connections taken from the shared pool
+    #[derive(Clone)]
+    pub struct RedisStore {
+        pool: RedisPool,
     }
 
-    pub fn get_request_vehicle(request_id: &str) -> Result<VehicleData, String>{
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
+    impl RedisStore {
+        pub fn new(pool: RedisPool) -> Self {
+            RedisStore { pool }
+        }
+
+        // Creates the missing indexes, fails if one doesn't match its schema
+        pub fn verify_indexes(&self) -> Result<Vec<&'static str>, ClassificatorError> {
+            let mut con = self.connection()?;
+            indexes::verify_indexes(&mut con)
+        }
 
-        let key = format!("part-request:{request_id}:vehicle");
-        let res: RedisResult<VehicleData> = con.hgetall(&key);
+        pub fn migrate_indexes(
+            &self,
+            force: bool,
+        ) -> Result<Vec<(&'static str, Migration)>, ClassificatorError> {
+            let mut con = self.connection()?;
+            indexes::migrate_indexes(&mut con, force)
+        }
 
-        if res.is_err(){
-            let err = format!("Error obtaining part request vehicle with id {}: {}", &key, res.as_ref().unwrap_err().to_string());
-            error!("{}", &err);
-            return Err(err);
+        pub fn backfill_request_fields(&self) -> Result<usize, ClassificatorError> {
+            let mut con = self.connection()?;
+            part_register::backfill_request_fields(&mut con)
         }
 
-        Ok(res.unwrap())
+        fn connection(&self) -> Result<PooledConnection<Client>, ClassificatorError> {
+            match self.pool.get() {
+                Ok(con) => Ok(con),
+                Err(err) => {
+                    let err = format!("Error obtaining redis connection: {}", err);
+                    error!("{}", err);
+                    Err(ClassificatorError::Storage(err))
+                }
+            }
+        }
     }
-    pub fn get_request_by_id(request_id: &str) -> Result<PartRequest, String>{
-        let client = create_client().unwrap();
-        let mut con = client.get_connection().unwrap();
 
-        let key = format!("part-request:{request_id}");
-        let res: RedisResult<PartRequest> = con.hgetall(&key);
+    impl LabelStore for RedisStore {
+        fn label_exists(&self, id: &str) -> Result<bool, ClassificatorError> {
+            let mut con = self.connection()?;
+            common::key_exists(&mut con, &format!("part-label:{}", id))
+        }
+
+        fn get_label(&self, id: &str) -> Result<Label, ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::get_label(&mut con, id)
+        }
+
+        fn get_all_labels(&self) -> Result<Vec<Label>, ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::get_all_labels(&mut con)
+        }
+
+        fn get_label_childs(&self, label_id: &str) -> Result<Vec<Label>, ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::get_label_childs(&mut con, label_id)
+        }
+
+        fn leaf_labels(&self, label_ids: &[String]) -> Result<Vec<String>, ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::leaf_labels(&mut con, label_ids)
+        }
+
+        fn create_label(
+            &self,
+            id: Option<&str>,
+            name: &str,
+            parent: &str,
+        ) -> Result<Label, ClassificatorError> {
+            let mut con = self.connection()?;
+            taxonomy::create_label(&mut con, id, name, parent)
+        }
+
+        fn update_label(
+            &self,
+            id: &str,
+            name: Option<&str>,
+            parent: Option<&str>,
+        ) -> Result<Label, ClassificatorError> {
+            let mut con = self.connection()?;
+            taxonomy::update_label(&mut con, id, name, parent)
+        }
 
-        if res.is_err(){
-            let err = format!("Error obtaining part request with id {}: {}", &key, res.as_ref().unwrap_err().to_string());
-            error!("{}", &err);
-            return Err(err);
+        fn delete_label(&self, id: &str, cascade: bool) -> Result<Vec<String>, ClassificatorError> {
+            let mut con = self.connection()?;
+            taxonomy::delete_label(&mut con, id, cascade)
         }
 
-        Ok(res.unwrap())
+        fn replace_labels(&self, labels: &[Label]) -> Result<Vec<String>, ClassificatorError> {
+            let mut con = self.connection()?;
+            taxonomy::replace_labels(&mut con, labels)
+        }
+    }
+
+    impl RequestStore for RedisStore {
+        fn request_exists(&self, id: &str) -> Result<bool, ClassificatorError> {
+            let mut con = self.connection()?;
+            common::key_exists(&mut con, &format!("part-request:{}", id))
+        }
+
+        fn create_part_request(&self, request: &PartRequest) -> Result<(), ClassificatorError> {
+            let mut con = self.connection()?;
+            part_register::create_part_request(&mut con, request)
+        }
+
+        fn find_request_by_reference(
+            &self,
+            origin: &str,
+            reference: &str,
+        ) -> Result<Option<String>, ClassificatorError> {
+            let mut con = self.connection()?;
+            part_register::find_request_by_reference(&mut con, origin, reference)
+        }
+
+        fn get_request_by_id(&self, request_id: &str) -> Result<PartRequest, ClassificatorError> {
+            let mut con = self.connection()?;
+            part_register::get_request_by_id(&mut con, request_id)
+        }
+
+        fn load_request(
+            &self,
+            request_id: &str,
+            include: &RequestInclude,
+        ) -> Result<RequestAggregate, ClassificatorError> {
+            let mut con = self.connection()?;
+            part_register::load_request(&mut con, request_id, include)
+        }
+
+        fn search_requests(
+            &self,
+            filter: &RequestFilter,
+        ) -> Result<RequestPage, ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::search_requests(&mut con, filter)
+        }
+
+        fn search_request_text(
+            &self,
+            text: &str,
+            offset: usize,
+            limit: usize,
+        ) -> Result<RequestMatches, ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::search_request_text(&mut con, text, offset, limit)
+        }
+
+        fn assign_label_tree(
+            &self,
+            request_id: &str,
+            label_id: &str,
+            actor: &str,
+        ) -> Result<Vec<Label>, ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::assign_label_tree(&mut con, request_id, label_id, actor)
+        }
+
+        fn remove_label_tree(
+            &self,
+            request_id: &str,
+            label_id: &str,
+            actor: &str,
+        ) -> Result<Vec<Label>, ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::remove_label_tree(&mut con, request_id, label_id, actor)
+        }
+
+        fn year_selection(
+            &self,
+            request_id: &str,
+            year: &str,
+            actor: &str,
+        ) -> Result<(), ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::year_selection(&mut con, request_id, year, actor)
+        }
+
+        fn set_request_status(
+            &self,
+            request_id: &str,
+            from: RequestStatus,
+            to: RequestStatus,
+            actor: &str,
+        ) -> Result<(), ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::set_request_status(&mut con, request_id, from, to, actor)
+        }
+
+        fn get_request_history(
+            &self,
+            request_id: &str,
+        ) -> Result<Vec<RequestEvent>, ClassificatorError> {
+            let mut con = self.connection()?;
+            classification::get_request_history(&mut con, request_id)
+        }
+
+        fn claim_next_request(
+            &self,
+            owner: &str,
+            ttl_ms: u64,
+        ) -> Result<Option<ClaimedRequest>, ClassificatorError> {
+            let mut con = self.connection()?;
+            claims::claim_next_request(&mut con, owner, ttl_ms)
+        }
+
+        fn renew_claim(
+            &self,
+            request_id: &str,
+            owner: &str,
+            ttl_ms: u64,
+        ) -> Result<Claim, ClassificatorError> {
+            let mut con = self.connection()?;
+            claims::renew_claim(&mut con, request_id, owner, ttl_ms)
+        }
+
+        fn release_claim(&self, request_id: &str, owner: &str) -> Result<(), ClassificatorError> {
+            let mut con = self.connection()?;
+            claims::release_claim(&mut con, request_id, owner)
+        }
+
+        fn get_claim(&self, request_id: &str) -> Result<Option<Claim>, ClassificatorError> {
+            let mut con = self.connection()?;
+            claims::get_claim(&mut con, request_id)
+        }
     }
 }
 
 #[cfg(test)]
 mod classification_test {
+    use crate::handlers;
+    use crate::storage::fixtures;
+    use crate::storage::LabelStore;
 
     // Passes if fails when trying to add a label that is already added.
     #[test]
     fn already_added_label() {
-        assert!(false)
+        let store = fixtures::store();
+
+        assert!(handlers::update_request_labels(
+            &store,
+            "req-1".to_string(),
+            "3".to_string(),
+            "tester",
+        )
+        .is_ok());
+        assert!(handlers::update_request_labels(
+            &store,
+            "req-1".to_string(),
+            "3".to_string(),
+            "tester",
+        )
+        .is_err());
     }
 
     // Checks the index search returns a parseable label struct
     #[test]
     fn search_returns_label() {
-        assert!(false)
+        let store = fixtures::store();
+        let labels = store.get_label_childs("0").unwrap();
+
+        let engine = labels.iter().find(|label| label.id == "1").unwrap();
+        assert_eq!(engine.name, "Engine");
+        assert_eq!(engine.parent, "0");
     }
 
     // Passes if given a label that is parent of other labels, return those labels.
     #[test]
     fn sublabel_found() {
-        assert!(false)
+        let store = fixtures::store();
+        let labels = handlers::get_labels(&store, "1".to_string()).unwrap();
+
+        let mut ids: Vec<String> = labels.into_iter().map(|label| label.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["2", "4"]);
     }
 
     // Passes if given a label that is not parent of other labels, return an empty vec.
     #[test]
     fn sublabel_not_found() {
-        assert!(false)
+        let store = fixtures::store();
+        let labels = handlers::get_labels(&store, "3".to_string()).unwrap();
+
+        assert!(labels.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod search_test {
+    use crate::redis::classification::request_query;
+    use crate::redis::search::QueryBuilder;
+    use crate::storage::RequestFilter;
+    use crate::structs::part_request::RequestStatus;
+
+    #[test]
+    fn empty_query_matches_all() {
+        assert_eq!(
+            QueryBuilder::new().range("timestamp", None, None).build(),
+            "*"
+        );
+    }
+
+    // Passes if user values can't add clauses to the query
+    #[test]
+    fn values_escaped() {
+        let query = QueryBuilder::new()
+            .tag("make", "Land Rover")
+            .tag("model", "x} | @origin:{EMAIL")
+            .build();
+
+        assert_eq!(
+            query,
+            r"@make:{Land\ Rover} @model:{x\}\ \|\ \@origin\:\{EMAIL}"
+        );
+    }
+
+    #[test]
+    fn text_terms() {
+        let query = QueryBuilder::new()
+            .terms("  radiador  toyota-corolla ")
+            .build();
+
+        assert_eq!(query, r"radiador toyota\-corolla");
+    }
+
+    #[test]
+    fn any_tag() {
+        let labels = [String::from("12"), String::from("frenos-1")];
+        let query = QueryBuilder::new().any_tag("labels", &labels).build();
+
+        assert_eq!(query, r"@labels:{12 | frenos\-1}");
+    }
+
+    #[test]
+    fn request_filters() {
+        let filter = RequestFilter {
+            status: Some(RequestStatus::Pending),
+            origin: Some(String::from("WHATSAPP")),
+            year: Some(String::from("2015")),
+            from: Some(1672531200000),
+            ..RequestFilter::default()
+        };
+
+        assert_eq!(
+            request_query(&filter),
+            "@classified:PENDING @origin:{WHATSAPP} @year:{2015} @timestamp:[1672531200000 +inf]"
+        );
+    }
+}
+
+#[cfg(test)]
+mod indexes_test {
+    use crate::redis::indexes::{IndexInfo, LABEL_INDEX, REQUEST_INDEX, SCHEMAS};
+    use redis::Value;
+
+    fn data(value: &str) -> Value {
+        Value::Data(value.as_bytes().to_vec())
+    }
+
+    fn words(values: &str) -> Value {
+        Value::Bulk(values.split_whitespace().map(data).collect())
+    }
+
+    // FT.INFO reply of label-parent-search as created by the service
+    fn label_info(parent: Value) -> Value {
+        Value::Bulk(vec![
+            data("index_name"),
+            data("label-parent-search"),
+            data("index_definition"),
+            Value::Bulk(vec![
+                data("key_type"),
+                data("HASH"),
+                data("prefixes"),
+                words("part-label:"),
+                data("default_score"),
+                data("1"),
+            ]),
+            data("attributes"),
+            Value::Bulk(vec![
+                words("identifier name attribute name type TEXT WEIGHT 1"),
+                parent,
+            ]),
+            data("num_docs"),
+            Value::Int(5),
+        ])
+    }
+
+    #[test]
+    fn create_args() {
+        let args = REQUEST_INDEX.create_args();
+
+        assert_eq!(
+            args[..8].join(" "),
+            "ON HASH PREFIX 1 part-request: FILTER exists(@classified) SCHEMA"
+        );
+        assert!(args.ends_with(&[
+            String::from("timestamp"),
+            String::from("NUMERIC"),
+            String::from("SORTABLE")
+        ]));
+    }
+
+    #[test]
+    fn current_index() {
+        let parent = words("identifier parent attribute parent type TEXT WEIGHT 1 NOSTEM");
+        let info = IndexInfo::parse(&label_info(parent));
+
+        assert_eq!(info.prefixes, vec!["part-label:"]);
+        assert!(LABEL_INDEX.differences(&info).is_empty());
+    }
+
+    // Passes if a changed field is reported, also with the attribute list before RediSearch 2.2
+    #[test]
+    fn outdated_index() {
+        let parent = words("parent type TAG SEPARATOR ,");
+        let info = IndexInfo::parse(&label_info(parent));
+
+        let differences = LABEL_INDEX.differences(&info);
+        assert_eq!(differences.len(), 1);
+        assert!(differences[0].starts_with("field 'parent'"));
+
+        // Prefix, filter, 8 missing fields and 2 undeclared ones
+        assert_eq!(REQUEST_INDEX.differences(&info).len(), 12);
+    }
+
+    #[test]
+    fn schema_names_unique() {
+        for (i, schema) in SCHEMAS.iter().enumerate() {
+            let others = &SCHEMAS[i + 1..];
+            assert!(others.iter().all(|other| other.name != schema.name));
+        }
     }
 }
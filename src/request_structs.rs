@@ -9,3 +9,133 @@ pub struct LabelUpdate {
 pub struct YearSelection {
     pub year_selected: String,
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NewLabel {
+    pub id: Option<String>,
+    pub name: String,
+    pub parent: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LabelChanges {
+    pub name: Option<String>,
+    pub parent: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LabelDeletion {
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LabelTreeQuery {
+    pub root: Option<String>,
+    pub depth: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LabelExport {
+    #[serde(default)]
+    pub format: LabelFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LabelImport {
+    #[serde(default)]
+    pub format: LabelFormat,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RequestQuery {
+    pub include: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatusChange {
+    pub status: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClaimQuery {
+    pub ttl_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TextSearchQuery {
+    pub q: String,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+// Filters of GET /request, from and to are unix timestamps in milliseconds
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RequestListQuery {
+    pub status: Option<String>,
+    pub origin: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub year: Option<String>,
+    pub requestor: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    #[serde(default)]
+    pub sort: SortOrder,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+// Request sent by the website or read from an email, it carries the vehicle and part data
+// the whatsapp workflow would otherwise store in tracker steps
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FormSubmission {
+    pub origin_system: String,
+    pub reference: String,
+    pub phone_number: String,
+    pub vin: String,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub description: Option<String>,
+    pub attached_files: Option<String>,
+}
+
+// Body of POST /request, reference is the counter ticket if there's one
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NewPartRequest {
+    pub reference: Option<String>,
+    pub requestor_id: String,
+    pub vin: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub year: Option<String>,
+    pub description: Option<String>,
+    pub attached_files: Option<String>,
+}
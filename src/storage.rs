@@ -0,0 +1,1090 @@
+use crate::errors::ClassificatorError;
+use crate::request_structs::SortOrder;
+use crate::structs::classification::Label;
+use crate::structs::part_request::{
+    Claim, ClaimedRequest, PartRequest, RequestEvent, RequestMatches, RequestPage, RequestStatus,
+};
+
+// Deepest label chain followed when walking up the ancestors, deeper chains are treated as cycles
+pub const MAX_LABEL_DEPTH: usize = 32;
+
+// Claim duration when none is requested, and the bounds accepted
+pub const DEFAULT_CLAIM_TTL_MS: u64 = 15 * 60 * 1000;
+pub const MIN_CLAIM_TTL_MS: u64 = 1000;
+pub const MAX_CLAIM_TTL_MS: u64 = 60 * 60 * 1000;
+
+// Page size of request searches when none is requested, and the largest one accepted
+pub const DEFAULT_PAGE_LIMIT: usize = 20;
+pub const MAX_PAGE_LIMIT: usize = 100;
+
+// Sub-resources loaded along with a part request
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RequestInclude {
+    pub vehicle: bool,
+    pub requestor: bool,
+    pub details: bool,
+    pub labels: bool,
+    pub history: bool,
+}
+
+impl RequestInclude {
+    pub fn all() -> Self {
+        RequestInclude {
+            vehicle: true,
+            requestor: true,
+            details: true,
+            labels: true,
+            history: true,
+        }
+    }
+
+    // Comma separated list of sub-resources, e.g. "vehicle,labels"
+    pub fn parse(include: &str) -> Result<Self, ClassificatorError> {
+        let mut parsed = RequestInclude::default();
+
+        for section in include.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match section {
+                "vehicle" => parsed.vehicle = true,
+                "requestor" => parsed.requestor = true,
+                "details" => parsed.details = true,
+                "labels" => parsed.labels = true,
+                "history" => parsed.history = true,
+                _ => {
+                    let err = format!("Unknown request include '{}'", section);
+                    return Err(ClassificatorError::Validation(err));
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+// Filters, order and page of a request search. Unset filters match every request, values are
+// compared exactly but ignoring case, from and to are inclusive timestamps in milliseconds
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RequestFilter {
+    pub status: Option<RequestStatus>,
+    pub origin: Option<String>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub year: Option<String>,
+    pub requestor: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub sort: SortOrder,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+// Part request with its sub-resources set, assigned labels are returned as stored
+#[derive(Debug, Clone)]
+pub struct RequestAggregate {
+    pub request: PartRequest,
+    pub labels: Vec<Label>,
+}
+
+// Storage operations over part labels, handlers depend on this instead of calling redis directly.
+pub trait LabelStore {
+    fn label_exists(&self, id: &str) -> Result<bool, ClassificatorError>;
+
+    fn get_label(&self, id: &str) -> Result<Label, ClassificatorError>;
+
+    fn get_all_labels(&self) -> Result<Vec<Label>, ClassificatorError>;
+
+    // Labels which parent corresponds to label_id (label-parent-search)
+    fn get_label_childs(&self, label_id: &str) -> Result<Vec<Label>, ClassificatorError>;
+
+    // Ids of the given labels without childs, in one round trip (label-parent-search)
+    fn leaf_labels(&self, label_ids: &[String]) -> Result<Vec<String>, ClassificatorError>;
+
+    // Creates the label under an existing parent, a numeric id is assigned when none is given
+    fn create_label(
+        &self,
+        id: Option<&str>,
+        name: &str,
+        parent: &str,
+    ) -> Result<Label, ClassificatorError>;
+
+    // Renames and/or re-parents the label, fails if the new parent is the label or one of its
+    // descendants
+    fn update_label(
+        &self,
+        id: &str,
+        name: Option<&str>,
+        parent: Option<&str>,
+    ) -> Result<Label, ClassificatorError>;
+
+    // Deletes the label and returns the deleted ids. Labels with childs or assigned to requests
+    // are only deleted on cascade, which also deletes the childs and unassigns them from requests
+    fn delete_label(&self, id: &str, cascade: bool) -> Result<Vec<String>, ClassificatorError>;
+
+    // Replaces the whole taxonomy in one step and returns the deleted ids. Fails without changes
+    // if a label to delete is assigned to a request
+    fn replace_labels(&self, labels: &[Label]) -> Result<Vec<String>, ClassificatorError>;
+}
+
+// Storage operations over part requests and their sub-resources.
+pub trait RequestStore {
+    fn request_exists(&self, id: &str) -> Result<bool, ClassificatorError>;
+
+    // Stores the request with the vehicle, details and requestor it holds in one step. Fails with
+    // a conflict without changes if a request with the same origin and reference exists
+    fn create_part_request(&self, request: &PartRequest) -> Result<(), ClassificatorError>;
+
+    // Id of the request created for the origin reference, if any
+    fn find_request_by_reference(
+        &self,
+        origin: &str,
+        reference: &str,
+    ) -> Result<Option<String>, ClassificatorError>;
+
+    fn get_request_by_id(&self, request_id: &str) -> Result<PartRequest, ClassificatorError>;
+
+    // Request with the included sub-resources in one round trip, missing ones are left as None
+    fn load_request(
+        &self,
+        request_id: &str,
+        include: &RequestInclude,
+    ) -> Result<RequestAggregate, ClassificatorError>;
+
+    // Page of the requests matching the filter sorted by timestamp, along with the total count
+    // of matches (request-search)
+    fn search_requests(&self, filter: &RequestFilter) -> Result<RequestPage, ClassificatorError>;
+
+    // Requests which description, VIN, make or model contain every word of the text
+    // (request-text-search)
+    fn search_request_text(
+        &self,
+        text: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<RequestMatches, ClassificatorError>;
+
+    // Assigns the label with all its ancestors in one step and returns the resulting labels.
+    // Fails without changes if the request or any label of the chain doesn't exist, or if the
+    // label is already assigned
+    fn assign_label_tree(
+        &self,
+        request_id: &str,
+        label_id: &str,
+        actor: &str,
+    ) -> Result<Vec<Label>, ClassificatorError>;
+
+    // Removes the label with all its assigned descendants in one step and returns the labels left.
+    // Fails if the label is not assigned, id 0 removes every label
+    fn remove_label_tree(
+        &self,
+        request_id: &str,
+        label_id: &str,
+        actor: &str,
+    ) -> Result<Vec<Label>, ClassificatorError>;
+
+    // Labels and year of requests which status isn't editable (RequestStatus::EDITABLE) can't
+    // be changed, assign_label_tree, remove_label_tree and year_selection fail with a conflict
+    fn year_selection(
+        &self,
+        request_id: &str,
+        year: &str,
+        actor: &str,
+    ) -> Result<(), ClassificatorError>;
+
+    // Moves the request to a new status only if it's still in `from`, fails with a conflict
+    // otherwise. Transition rules are checked by the caller
+    fn set_request_status(
+        &self,
+        request_id: &str,
+        from: RequestStatus,
+        to: RequestStatus,
+        actor: &str,
+    ) -> Result<(), ClassificatorError>;
+
+    // Changes recorded on `part-request:{id}:events`, oldest first. Label, year and status
+    // changes are recorded by the same call that applies them
+    fn get_request_history(
+        &self,
+        request_id: &str,
+    ) -> Result<Vec<RequestEvent>, ClassificatorError>;
+
+    // Claims the oldest PENDING request nobody holds, None when the queue is empty. Claims
+    // expire after the ttl so abandoned requests go back to the queue
+    fn claim_next_request(
+        &self,
+        owner: &str,
+        ttl_ms: u64,
+    ) -> Result<Option<ClaimedRequest>, ClassificatorError>;
+
+    // Renew and release fail with NOT_FOUND without a live claim, CONFLICT if held by another
+    fn renew_claim(
+        &self,
+        request_id: &str,
+        owner: &str,
+        ttl_ms: u64,
+    ) -> Result<Claim, ClassificatorError>;
+
+    fn release_claim(&self, request_id: &str, owner: &str) -> Result<(), ClassificatorError>;
+
+    fn get_claim(&self, request_id: &str) -> Result<Option<Claim>, ClassificatorError>;
+}
+
+// Only used by tests, the service always stores in redis. Kept out of the release build on
+// purpose: the HTTP routes and the request sources are bound to the redis pool, so running the
+// service on it would need both made generic first
+#[cfg(test)]
+pub mod memory {
+    use super::{
+        LabelStore, RequestAggregate, RequestFilter, RequestInclude, RequestStore, MAX_LABEL_DEPTH,
+    };
+    use crate::errors::ClassificatorError;
+    use crate::request_structs::SortOrder;
+    use crate::structs::classification::Label;
+    use crate::structs::part_request::{
+        now_millis, Claim, ClaimedRequest, PartRequest, RequestAction, RequestDetails,
+        RequestEvent, RequestMatch, RequestMatches, RequestPage, RequestStatus, Requestor,
+        VehicleData, YearSource,
+    };
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+    use std::sync::{Mutex, MutexGuard};
+
+    #[derive(Default)]
+    struct MemoryData {
+        labels: BTreeMap<String, Label>,
+        requests: BTreeMap<String, PartRequest>,
+        vehicles: HashMap<String, VehicleData>,
+        details: HashMap<String, RequestDetails>,
+        requestors: HashMap<String, Requestor>,
+        request_labels: HashMap<String, BTreeSet<String>>,
+        events: HashMap<String, Vec<RequestEvent>>,
+        // Owner and expiration (ms) of every claim, expired ones are ignored
+        claims: HashMap<String, (String, u128)>,
+        label_sequence: u64,
+    }
+
+    // Store kept entirely in process memory, mirrors the semantics of the redis implementation
+    // (including the request-search and label-parent-search queries) so handlers can run offline.
+    #[derive(Default)]
+    pub struct InMemoryStore {
+        data: Mutex<MemoryData>,
+    }
+
+    impl InMemoryStore {
+        pub fn new() -> Self {
+            InMemoryStore::default()
+        }
+
+        pub fn insert_label(&self, label: Label) {
+            self.lock().labels.insert(label.id.clone(), label);
+        }
+
+        pub fn insert_request(&self, request: PartRequest) {
+            self.lock().insert_request(&request);
+        }
+
+        fn lock(&self) -> MutexGuard<'_, MemoryData> {
+            self.data.lock().expect("In memory store lock poisoned")
+        }
+    }
+
+    impl MemoryData {
+        // Lowercase words of the text, the way the text index tokenizes it (without stemming)
+        fn words(text: &str) -> Vec<String> {
+            text.split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .map(str::to_lowercase)
+                .collect()
+        }
+
+        // Wraps the words found in terms with <em> tags, like the HIGHLIGHT option of FT.SEARCH
+        fn highlight(text: &str, terms: &[String]) -> String {
+            let mut highlighted = String::with_capacity(text.len());
+            let mut word = String::new();
+
+            for c in text.chars().chain(std::iter::once(' ')) {
+                if c.is_alphanumeric() {
+                    word.push(c);
+                    continue;
+                }
+
+                if terms.contains(&word.to_lowercase()) {
+                    highlighted.push_str(&format!("<em>{}</em>", word));
+                } else {
+                    highlighted.push_str(&word);
+                }
+                word.clear();
+                highlighted.push(c);
+            }
+
+            highlighted.pop();
+            highlighted
+        }
+
+        // Walks up from parent, failing if it is missing or if the moved label is found on the way
+        fn verify_parent(
+            &self,
+            parent: &str,
+            moved: Option<&str>,
+        ) -> Result<(), ClassificatorError> {
+            let mut current = parent;
+            let mut depth = 0;
+
+            while current != "0" {
+                if let Some(label_id) = moved.filter(|label_id| *label_id == current) {
+                    return Err(ClassificatorError::Conflict(format!(
+                        "Label '{label_id}' cannot be moved under itself or its descendants"
+                    )));
+                }
+
+                depth += 1;
+                if depth > MAX_LABEL_DEPTH {
+                    return Err(ClassificatorError::Storage(format!(
+                        "Label '{parent}' ancestors contain a cycle"
+                    )));
+                }
+
+                match self.labels.get(current) {
+                    Some(label) => current = &label.parent,
+                    None => {
+                        let err = format!("Parent label '{}' doesnt exist", current);
+                        return Err(ClassificatorError::NotFound(err));
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
+        // Same matching as the request-search query built by redis::classification::request_query
+        fn matches(&self, request: &PartRequest, filter: &RequestFilter) -> bool {
+            let vehicle = self.vehicles.get(&request.id);
+            let requestor = self.requestors.get(&request.id);
+            let timestamp: u64 = request.timestamp.parse().unwrap_or(0);
+
+            let fields = [
+                (&filter.origin, Some(&request.origin)),
+                (&filter.make, vehicle.and_then(|v| v.make.as_ref())),
+                (&filter.model, vehicle.and_then(|v| v.model.as_ref())),
+                (&filter.year, vehicle.and_then(|v| v.year.as_ref())),
+                (&filter.requestor, requestor.map(|r| &r.user_id)),
+            ];
+
+            filter
+                .status
+                .is_none_or(|status| request.classified == status)
+                && fields.iter().all(|(expected, value)| {
+                    expected.as_ref().is_none_or(|expected| {
+                        value.is_some_and(|value| value.eq_ignore_ascii_case(expected))
+                    })
+                })
+                && filter.from.is_none_or(|from| timestamp >= from)
+                && filter.to.is_none_or(|to| timestamp <= to)
+        }
+
+        // Sub-resources are kept apart, like the redis hashes
+        fn insert_request(&mut self, request: &PartRequest) {
+            let id = &request.id;
+            if let Some(vehicle) = &request.vehicle {
+                self.vehicles.insert(id.clone(), vehicle.clone());
+            }
+            if let Some(details) = &request.details {
+                self.details.insert(id.clone(), details.clone());
+            }
+            if let Some(requestor) = &request.requestor {
+                self.requestors.insert(id.clone(), requestor.clone());
+            }
+
+            let base = PartRequest::new(
+                id,
+                &request.origin,
+                &request.origin_reference,
+                &request.timestamp,
+                request.classified,
+            );
+            self.requests.insert(id.clone(), base);
+        }
+
+        fn find_reference(&self, origin: &str, reference: &str) -> Option<String> {
+            self.requests
+                .values()
+                .find(|request| request.origin == origin && request.origin_reference == reference)
+                .map(|request| request.id.clone())
+        }
+
+        fn verify_editable(&self, request_id: &str, what: &str) -> Result<(), ClassificatorError> {
+            match self.requests.get(request_id) {
+                Some(request) if !RequestStatus::EDITABLE.contains(&request.classified) => {
+                    Err(ClassificatorError::Conflict(format!(
+                        "Part request '{}' is {}, its {} cant be changed",
+                        request_id, request.classified, what
+                    )))
+                }
+                _ => Ok(()),
+            }
+        }
+
+        fn verify_request(&self, request_id: &str) -> Result<(), ClassificatorError> {
+            if !self.requests.contains_key(request_id) {
+                return Err(ClassificatorError::NotFound(format!(
+                    "Part request '{}' doesnt exist",
+                    request_id
+                )));
+            }
+
+            Ok(())
+        }
+
+        fn request_labels(&self, request_id: &str) -> Result<Vec<Label>, ClassificatorError> {
+            let mut list: Vec<Label> = vec![];
+
+            if let Some(ids) = self.request_labels.get(request_id) {
+                for id in ids {
+                    match self.labels.get(id) {
+                        Some(label) => list.push(label.clone()),
+                        None => {
+                            return Err(ClassificatorError::NotFound(format!(
+                                "Label '{}' doesnt exist",
+                                id
+                            )))
+                        }
+                    }
+                }
+            }
+
+            Ok(list)
+        }
+
+        fn live_claim(&self, request_id: &str) -> Option<Claim> {
+            let now: u128 = now_millis().parse().unwrap_or_default();
+
+            match self.claims.get(request_id) {
+                Some((owner, expires_at)) if *expires_at > now => Some(Claim {
+                    request_id: request_id.to_string(),
+                    owner: owner.clone(),
+                    expires_at: expires_at.to_string(),
+                }),
+                _ => None,
+            }
+        }
+
+        fn claim(&mut self, request_id: &str, owner: &str, ttl_ms: u64) -> Claim {
+            let claim = Claim::new(request_id, owner, ttl_ms);
+            let expires_at = claim.expires_at.parse().unwrap_or_default();
+            self.claims
+                .insert(request_id.to_string(), (owner.to_string(), expires_at));
+
+            claim
+        }
+
+        fn verify_claim_owner(
+            &self,
+            request_id: &str,
+            owner: &str,
+        ) -> Result<(), ClassificatorError> {
+            match self.live_claim(request_id) {
+                Some(claim) if claim.owner == owner => Ok(()),
+                Some(claim) => Err(ClassificatorError::Conflict(format!(
+                    "Part request '{}' is claimed by {}",
+                    request_id, claim.owner
+                ))),
+                None => Err(ClassificatorError::NotFound(format!(
+                    "Part request '{}' is not claimed",
+                    request_id
+                ))),
+            }
+        }
+
+        fn assigned_ids(&self, request_id: &str) -> String {
+            match self.request_labels.get(request_id) {
+                Some(ids) => ids.iter().cloned().collect::<Vec<String>>().join(","),
+                None => String::new(),
+            }
+        }
+
+        // Entry ids follow the redis stream format, timestamp-sequence
+        fn record_event(
+            &mut self,
+            request_id: &str,
+            actor: &str,
+            action: RequestAction,
+            before: String,
+            after: String,
+        ) {
+            let events = self.events.entry(request_id.to_string()).or_default();
+            let timestamp = now_millis();
+
+            events.push(RequestEvent {
+                id: format!("{}-{}", timestamp, events.len()),
+                actor: actor.to_string(),
+                timestamp,
+                action,
+                before,
+                after,
+            });
+        }
+    }
+
+    impl LabelStore for InMemoryStore {
+        fn label_exists(&self, id: &str) -> Result<bool, ClassificatorError> {
+            Ok(self.lock().labels.contains_key(id))
+        }
+
+        fn get_label(&self, id: &str) -> Result<Label, ClassificatorError> {
+            match self.lock().labels.get(id) {
+                Some(label) => Ok(label.clone()),
+                None => Err(ClassificatorError::NotFound(format!(
+                    "Label '{}' doesnt exist",
+                    id
+                ))),
+            }
+        }
+
+        fn get_all_labels(&self) -> Result<Vec<Label>, ClassificatorError> {
+            Ok(self.lock().labels.values().cloned().collect())
+        }
+
+        fn get_label_childs(&self, label_id: &str) -> Result<Vec<Label>, ClassificatorError> {
+            Ok(self
+                .lock()
+                .labels
+                .values()
+                .filter(|label| label.parent == label_id)
+                .cloned()
+                .collect())
+        }
+
+        fn leaf_labels(&self, label_ids: &[String]) -> Result<Vec<String>, ClassificatorError> {
+            let data = self.lock();
+            Ok(label_ids
+                .iter()
+                .filter(|id| !data.labels.values().any(|label| &label.parent == *id))
+                .cloned()
+                .collect())
+        }
+
+        fn create_label(
+            &self,
+            id: Option<&str>,
+            name: &str,
+            parent: &str,
+        ) -> Result<Label, ClassificatorError> {
+            let mut data = self.lock();
+            data.verify_parent(parent, None)?;
+
+            let id = match id {
+                Some(id) if data.labels.contains_key(id) => {
+                    let err = format!("Label '{}' already exists", id);
+                    return Err(ClassificatorError::Conflict(err));
+                }
+                Some(id) => id.to_string(),
+                None => loop {
+                    data.label_sequence += 1;
+                    let id = data.label_sequence.to_string();
+                    if !data.labels.contains_key(&id) {
+                        break id;
+                    }
+                },
+            };
+
+            let label = Label::new(&id, name, parent);
+            data.labels.insert(id, label.clone());
+            Ok(label)
+        }
+
+        fn update_label(
+            &self,
+            id: &str,
+            name: Option<&str>,
+            parent: Option<&str>,
+        ) -> Result<Label, ClassificatorError> {
+            let mut data = self.lock();
+
+            if !data.labels.contains_key(id) {
+                let err = format!("Label '{}' doesnt exist", id);
+                return Err(ClassificatorError::NotFound(err));
+            }
+
+            if let Some(parent) = parent {
+                data.verify_parent(parent, Some(id))?;
+            }
+
+            let label = data.labels.get_mut(id).unwrap();
+            if let Some(name) = name {
+                label.name = name.to_string();
+            }
+            if let Some(parent) = parent {
+                label.parent = parent.to_string();
+            }
+
+            Ok(label.clone())
+        }
+
+        fn delete_label(&self, id: &str, cascade: bool) -> Result<Vec<String>, ClassificatorError> {
+            let mut data = self.lock();
+
+            if !data.labels.contains_key(id) {
+                let err = format!("Label '{}' doesnt exist", id);
+                return Err(ClassificatorError::NotFound(err));
+            }
+
+            let mut subtree: BTreeSet<String> = BTreeSet::from([id.to_string()]);
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for label in data.labels.values() {
+                    if !subtree.contains(&label.id) && subtree.contains(&label.parent) {
+                        subtree.insert(label.id.clone());
+                        changed = true;
+                    }
+                }
+            }
+
+            if !cascade && subtree.len() > 1 {
+                return Err(ClassificatorError::Conflict(format!(
+                    "Label '{id}' has child labels, use cascade to delete them"
+                )));
+            }
+
+            let in_use = data
+                .request_labels
+                .values()
+                .filter(|labels| !labels.is_disjoint(&subtree))
+                .count();
+
+            if !cascade && in_use > 0 {
+                return Err(ClassificatorError::Conflict(format!(
+                    "Label '{id}' is assigned to {in_use} requests, use cascade to unassign it"
+                )));
+            }
+
+            for labels in data.request_labels.values_mut() {
+                labels.retain(|label| !subtree.contains(label));
+            }
+            for label in &subtree {
+                data.labels.remove(label);
+            }
+
+            Ok(subtree.into_iter().collect())
+        }
+
+        fn replace_labels(&self, labels: &[Label]) -> Result<Vec<String>, ClassificatorError> {
+            let mut data = self.lock();
+
+            let deleted: Vec<String> = data
+                .labels
+                .keys()
+                .filter(|id| !labels.iter().any(|label| &label.id == *id))
+                .cloned()
+                .collect();
+
+            let in_use: Vec<&str> = deleted
+                .iter()
+                .filter(|id| data.request_labels.values().any(|set| set.contains(*id)))
+                .map(String::as_str)
+                .collect();
+
+            if !in_use.is_empty() {
+                return Err(ClassificatorError::Conflict(format!(
+                    "Labels assigned to requests cant be deleted: {}",
+                    in_use.join(", ")
+                )));
+            }
+
+            data.labels = labels
+                .iter()
+                .map(|label| (label.id.clone(), label.clone()))
+                .collect();
+
+            Ok(deleted)
+        }
+    }
+
+    impl RequestStore for InMemoryStore {
+        fn request_exists(&self, id: &str) -> Result<bool, ClassificatorError> {
+            Ok(self.lock().requests.contains_key(id))
+        }
+
+        fn create_part_request(&self, request: &PartRequest) -> Result<(), ClassificatorError> {
+            let mut data = self.lock();
+
+            let existing = data.find_reference(&request.origin, &request.origin_reference);
+            if let Some(existing) = existing {
+                return Err(ClassificatorError::Conflict(format!(
+                    "Part request {} already has this origin reference",
+                    existing
+                )));
+            }
+
+            data.insert_request(request);
+            Ok(())
+        }
+
+        fn find_request_by_reference(
+            &self,
+            origin: &str,
+            reference: &str,
+        ) -> Result<Option<String>, ClassificatorError> {
+            Ok(self.lock().find_reference(origin, reference))
+        }
+
+        fn get_request_by_id(&self, request_id: &str) -> Result<PartRequest, ClassificatorError> {
+            match self.lock().requests.get(request_id) {
+                Some(request) => Ok(request.clone()),
+                None => Err(ClassificatorError::NotFound(format!(
+                    "Part request '{}' doesnt exist",
+                    request_id
+                ))),
+            }
+        }
+
+        fn load_request(
+            &self,
+            request_id: &str,
+            include: &RequestInclude,
+        ) -> Result<RequestAggregate, ClassificatorError> {
+            let data = self.lock();
+            data.verify_request(request_id)?;
+
+            let mut request = data.requests[request_id].clone();
+            if include.vehicle {
+                request.vehicle = data.vehicles.get(request_id).cloned();
+            }
+            if include.requestor {
+                request.requestor = data.requestors.get(request_id).cloned();
+            }
+            if include.details {
+                request.details = data.details.get(request_id).cloned();
+            }
+            if include.history {
+                request.history = Some(data.events.get(request_id).cloned().unwrap_or_default());
+            }
+
+            let labels = match include.labels {
+                true => data.request_labels(request_id)?,
+                false => vec![],
+            };
+
+            Ok(RequestAggregate { request, labels })
+        }
+
+        fn search_requests(
+            &self,
+            filter: &RequestFilter,
+        ) -> Result<RequestPage, ClassificatorError> {
+            let data = self.lock();
+            let mut matches: Vec<(u128, &PartRequest)> = data
+                .requests
+                .values()
+                .filter(|request| data.matches(request, filter))
+                .map(|request| (request.timestamp.parse().unwrap_or(0), request))
+                .collect();
+
+            matches.sort_by_key(|(timestamp, _)| *timestamp);
+            if filter.sort == SortOrder::Desc {
+                matches.reverse();
+            }
+
+            Ok(RequestPage {
+                total: matches.len(),
+                offset: filter.offset,
+                limit: filter.limit,
+                requests: matches
+                    .into_iter()
+                    .skip(filter.offset)
+                    .take(filter.limit)
+                    .map(|(_, request)| request.clone())
+                    .collect(),
+            })
+        }
+
+        fn search_request_text(
+            &self,
+            text: &str,
+            offset: usize,
+            limit: usize,
+        ) -> Result<RequestMatches, ClassificatorError> {
+            let data = self.lock();
+            let terms = MemoryData::words(text);
+            let mut matches: Vec<RequestMatch> = vec![];
+
+            for request_id in data.requests.keys() {
+                let details = data.details.get(request_id);
+                let vehicle = data.vehicles.get(request_id);
+                let fields = [
+                    details.and_then(|d| d.description.as_deref()),
+                    vehicle.and_then(|v| v.vin.as_deref()),
+                    vehicle.and_then(|v| v.make.as_deref()),
+                    vehicle.and_then(|v| v.model.as_deref()),
+                ];
+
+                let found = terms.iter().all(|term| {
+                    fields
+                        .iter()
+                        .flatten()
+                        .any(|field| MemoryData::words(field).contains(term))
+                });
+                if !found {
+                    continue;
+                }
+
+                let [description, vin, make, model] =
+                    fields.map(|field| field.map(|field| MemoryData::highlight(field, &terms)));
+                matches.push(RequestMatch {
+                    request_id: request_id.clone(),
+                    description,
+                    vin,
+                    make,
+                    model,
+                    labels: data
+                        .request_labels
+                        .get(request_id)
+                        .map(|ids| ids.iter().cloned().collect())
+                        .unwrap_or_default(),
+                });
+            }
+
+            Ok(RequestMatches {
+                total: matches.len(),
+                offset,
+                limit,
+                matches: matches.into_iter().skip(offset).take(limit).collect(),
+            })
+        }
+
+        fn assign_label_tree(
+            &self,
+            request_id: &str,
+            label_id: &str,
+            actor: &str,
+        ) -> Result<Vec<Label>, ClassificatorError> {
+            let mut data = self.lock();
+            data.verify_request(request_id)?;
+            data.verify_editable(request_id, "labels")?;
+
+            if !data.labels.contains_key(label_id) {
+                let err = format!("Label '{}' doesnt exist", label_id);
+                return Err(ClassificatorError::NotFound(err));
+            }
+
+            let assigned = data.request_labels.get(request_id);
+            if assigned.is_some_and(|labels| labels.contains(label_id)) {
+                return Err(ClassificatorError::Conflict(format!(
+                    "Label '{label_id}' is already assigned to request"
+                )));
+            }
+
+            let mut chain: Vec<String> = vec![];
+            let mut current = label_id.to_string();
+            while current != "0" {
+                if chain.len() >= MAX_LABEL_DEPTH {
+                    return Err(ClassificatorError::Storage(format!(
+                        "Label '{label_id}' ancestors contain a cycle"
+                    )));
+                }
+
+                match data.labels.get(&current) {
+                    Some(label) => {
+                        chain.push(current);
+                        current = label.parent.clone();
+                    }
+                    None => {
+                        let err = format!("Parent label '{}' doesnt exist", current);
+                        return Err(ClassificatorError::NotFound(err));
+                    }
+                }
+            }
+
+            let before = data.assigned_ids(request_id);
+            data.request_labels
+                .entry(request_id.to_string())
+                .or_default()
+                .extend(chain);
+            let after = data.assigned_ids(request_id);
+            let action = RequestAction::LabelAdded;
+            data.record_event(request_id, actor, action, before, after);
+
+            data.request_labels(request_id)
+        }
+
+        fn remove_label_tree(
+            &self,
+            request_id: &str,
+            label_id: &str,
+            actor: &str,
+        ) -> Result<Vec<Label>, ClassificatorError> {
+            let mut data = self.lock();
+            data.verify_request(request_id)?;
+            data.verify_editable(request_id, "labels")?;
+
+            let assigned = data
+                .request_labels
+                .get(request_id)
+                .cloned()
+                .unwrap_or_default();
+
+            // Id 0 is a symbolic id for base labels
+            if label_id != "0" && !assigned.contains(label_id) {
+                return Err(ClassificatorError::Conflict(format!(
+                    "Label '{label_id}' is not assigned to request"
+                )));
+            }
+
+            let mut removed: BTreeSet<String> = BTreeSet::from([label_id.to_string()]);
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for id in &assigned {
+                    let parent = data.labels.get(id).map(|label| &label.parent);
+                    if !removed.contains(id) && parent.is_some_and(|p| removed.contains(p)) {
+                        removed.insert(id.clone());
+                        changed = true;
+                    }
+                }
+            }
+
+            let before = data.assigned_ids(request_id);
+            if let Some(labels) = data.request_labels.get_mut(request_id) {
+                labels.retain(|id| !removed.contains(id));
+            }
+            let after = data.assigned_ids(request_id);
+            let action = RequestAction::LabelRemoved;
+            data.record_event(request_id, actor, action, before, after);
+
+            data.request_labels(request_id)
+        }
+
+        fn year_selection(
+            &self,
+            request_id: &str,
+            year: &str,
+            actor: &str,
+        ) -> Result<(), ClassificatorError> {
+            let mut data = self.lock();
+            data.verify_request(request_id)?;
+            data.verify_editable(request_id, "year")?;
+            let vehicle = data
+                .vehicles
+                .entry(request_id.to_string())
+                .or_insert_with(|| VehicleData::new("", "", "", ""));
+            let before = vehicle.year.replace(year.to_string()).unwrap_or_default();
+            vehicle.year_source = Some(YearSource::Manual);
+
+            let action = RequestAction::YearSelected;
+            data.record_event(request_id, actor, action, before, year.to_string());
+            Ok(())
+        }
+
+        fn set_request_status(
+            &self,
+            request_id: &str,
+            from: RequestStatus,
+            to: RequestStatus,
+            actor: &str,
+        ) -> Result<(), ClassificatorError> {
+            let mut data = self.lock();
+            match data.requests.get_mut(request_id) {
+                Some(request) if request.classified == from => {
+                    request.classified = to;
+
+                    let (before, after) = (from.to_string(), to.to_string());
+                    let action = RequestAction::StatusChanged;
+                    data.record_event(request_id, actor, action, before, after);
+                    Ok(())
+                }
+                Some(request) => Err(ClassificatorError::Conflict(format!(
+                    "Part request '{}' status changed to {}",
+                    request_id, request.classified
+                ))),
+                None => Err(ClassificatorError::NotFound(format!(
+                    "Part request '{}' doesnt exist",
+                    request_id
+                ))),
+            }
+        }
+
+        fn get_request_history(
+            &self,
+            request_id: &str,
+        ) -> Result<Vec<RequestEvent>, ClassificatorError> {
+            let data = self.lock();
+            data.verify_request(request_id)?;
+
+            Ok(data.events.get(request_id).cloned().unwrap_or_default())
+        }
+
+        fn claim_next_request(
+            &self,
+            owner: &str,
+            ttl_ms: u64,
+        ) -> Result<Option<ClaimedRequest>, ClassificatorError> {
+            let mut data = self.lock();
+
+            let mut pending: Vec<PartRequest> = data
+                .requests
+                .values()
+                .filter(|request| request.classified == RequestStatus::Pending)
+                .filter(|request| data.live_claim(&request.id).is_none())
+                .cloned()
+                .collect();
+            pending.sort_by_key(|request| request.timestamp.parse::<u128>().unwrap_or_default());
+
+            match pending.into_iter().next() {
+                Some(request) => {
+                    let claim = data.claim(&request.id, owner, ttl_ms);
+                    Ok(Some(ClaimedRequest { claim, request }))
+                }
+                None => Ok(None),
+            }
+        }
+
+        fn renew_claim(
+            &self,
+            request_id: &str,
+            owner: &str,
+            ttl_ms: u64,
+        ) -> Result<Claim, ClassificatorError> {
+            let mut data = self.lock();
+            data.verify_claim_owner(request_id, owner)?;
+
+            Ok(data.claim(request_id, owner, ttl_ms))
+        }
+
+        fn release_claim(&self, request_id: &str, owner: &str) -> Result<(), ClassificatorError> {
+            let mut data = self.lock();
+            data.verify_claim_owner(request_id, owner)?;
+
+            data.claims.remove(request_id);
+            Ok(())
+        }
+
+        fn get_claim(&self, request_id: &str) -> Result<Option<Claim>, ClassificatorError> {
+            Ok(self.lock().live_claim(request_id))
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    use super::memory::InMemoryStore;
+    use crate::structs::classification::Label;
+    use crate::structs::part_request::{PartRequest, RequestStatus};
+
+    // Engine > Cooling > Radiator, Engine > Ignition and Brakes
+    pub fn store() -> InMemoryStore {
+        let store = InMemoryStore::new();
+
+        store.insert_label(Label::new("1", "Engine", "0"));
+        store.insert_label(Label::new("2", "Cooling", "1"));
+        store.insert_label(Label::new("3", "Radiator", "2"));
+        store.insert_label(Label::new("4", "Ignition", "1"));
+        store.insert_label(Label::new("5", "Brakes", "0"));
+
+        store.insert_request(PartRequest::new(
+            "req-1",
+            "WHATSAPP",
+            "tracker-1",
+            "1672531200000",
+            RequestStatus::Pending,
+        ));
+
+        store
+    }
+}
@@ -1,105 +1,45 @@
-use crate::structs::constants::get_year_encodings;
-use fizzy_commons::redis::client::create_client;
+use crate::redis::indexes::TRACKER_INDEX;
+use crate::redis::pool::RedisPool;
+use crate::request_structs::FormSubmission;
+use crate::structs::decode::{documents, HashFields};
 use fizzy_commons::shared_structs::user_management::User;
-use log::{debug, info};
-use redis::Value::Bulk;
-use redis::{RedisResult, Value};
-use std::collections::HashMap;
+use log::{debug, error};
+use r2d2::PooledConnection;
+use redis::{Client, FromRedisValue, RedisResult, Value};
 
 const MAKE_STATUS_ID: i32 = 3;
 const DESCRIPTION_STATUS_ID: i32 = 9;
 const VIN_STATUS_ID: i32 = 7;
 const MODEL_STATUS_ID: i32 = 5;
-const VIN_YEAR_DIGIT: i32 = 9; // 10th digit of the VIN
-
-pub struct VinInformation {
-    make: String,
-    model: String,
-    year: String,
-    plant: String,
-}
 
 #[derive(Clone, Debug)]
 pub struct TrackerStep {
     pub(crate) tracker_id: String,
     pub(crate) timestamp: String,
-    pub(crate) id: String,
     pub(crate) status: String,
     pub(crate) value: String,
     pub(crate) attached_files: String,
-    pub(crate) message_reference: String,
-}
-
-pub struct TrackerParam {
-    pub tracker_id: String,
 }
 
-impl TrackerStep {
-    pub fn parse_from_redis(&mut self, register: &Vec<Value>) -> TrackerStep {
-        let mut values: HashMap<String, String> = HashMap::new();
-
-        // Parse bulk into key-val hashmap
-        let mut param_name = "".to_string();
-        for (index, elem) in register.iter().enumerate() {
-            let string_val = match elem {
-                Value::Data(val) => String::from_utf8(val.clone()),
-                _ => {
-                    panic!("Unexpected value")
-                }
-            }
-            .unwrap();
-
-            if index % 2 == 0 {
-                param_name = string_val;
-            } else {
-                values.insert(param_name.to_string(), string_val);
-            }
-        }
+impl FromRedisValue for TrackerStep {
+    fn from_redis_values(items: &[Value]) -> RedisResult<Vec<Self>> {
+        documents(items)
+    }
 
-        // Add parsed values to struct
-        self.value = String::from(
-            values
-                .get("value")
-                .expect("Expected parameter value wasn't found"),
-        );
-        values.remove("value");
-        self.status = String::from(
-            values
-                .get("status")
-                .expect("Expected parameter status wasn't found"),
-        );
-        values.remove("status");
-        self.tracker_id = String::from(
-            values
-                .get("tracker_id")
-                .expect("Expected parameter tracker_id wasn't found"),
-        );
-        values.remove("tracker_id");
-        self.timestamp = String::from(
-            values
-                .get("timestamp")
-                .expect("Expected parameter timestamp wasn't found"),
-        );
-        values.remove("timestamp");
-        self.attached_files = String::from(
-            values
-                .get("attached_files")
-                .expect("Expected parameter attached_files wasn't found"),
-        );
-        values.remove("attached_files");
-        self.message_reference = String::from(
-            values
-                .get("message_reference")
-                .expect("Expected parameter message_reference wasn't found"),
-        );
-        values.remove("message_reference");
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        let mut fields = HashFields::parse("TrackerStep", v)?;
 
-        // Fails it there are values in the hashmap that are not parsed into the tracker step struct
-        if values.iter().len() > 0 {
-            panic!("Found more values than expected");
-        }
+        let step = TrackerStep {
+            tracker_id: fields.required("tracker_id"),
+            timestamp: fields.required("timestamp"),
+            status: fields.required("status"),
+            value: fields.required("value"),
+            // Not every step of the workflow stores files
+            attached_files: fields.optional("attached_files").unwrap_or_default(),
+        };
+        fields.finish()?;
 
-        self.clone()
+        Ok(step)
     }
 }
 
@@ -108,231 +48,472 @@ impl Default for TrackerStep {
         TrackerStep {
             tracker_id: "".to_string(),
             timestamp: "".to_string(),
-            id: "".to_string(),
             status: "".to_string(),
             value: "".to_string(),
             attached_files: "".to_string(),
-            message_reference: "".to_string(),
         }
     }
 }
 
 pub trait Source {
-    fn get_vin(reference: &str) -> Option<String>;
-    fn get_description(reference: &str) -> Option<String>;
-    fn get_attached_files(reference: &str) -> Option<String>;
+    fn get_vin(&self, reference: &str) -> Option<String>;
+    fn get_description(&self, reference: &str) -> Option<String>;
+    fn get_attached_files(&self, reference: &str) -> Option<String>;
+
+    fn get_make(&self, tracker_id: &str) -> Option<String>;
+
+    fn get_requestor(&self, reference: &str) -> Result<User, String>;
 
-    fn decode_year(vin: &str) -> (String, String) {
-        let digit_value = vin
-            .chars()
-            .nth(VIN_YEAR_DIGIT as usize)
-            .unwrap()
-            .to_string();
-        debug!("Year digit is {digit_value}");
-        let year_table = get_year_encodings();
-        let possible_years = year_table.get(&digit_value.to_ascii_uppercase());
+    fn get_model(&self, tracker_id: &str) -> Option<String>;
+}
+
+// Reads the steps the whatsapp workflow stored for a tracker (indexes::TRACKER_INDEX)
+#[derive(Clone)]
+pub struct WhatsappSource {
+    pool: RedisPool,
+}
 
-        if possible_years.is_none() {
-            panic!("Year digit is not a valid digit");
+impl WhatsappSource {
+    pub fn new(pool: RedisPool) -> Self {
+        WhatsappSource { pool }
+    }
+
+    fn connection(&self) -> Option<PooledConnection<Client>> {
+        match self.pool.get() {
+            Ok(con) => Some(con),
+            Err(err) => {
+                error!("Error obtaining redis connection: {}", err);
+                None
+            }
         }
+    }
+
+    // Steps stored for the tracker with the given status, oldest first. None if they couldn't be
+    // retrieved
+    fn find_steps(&self, tracker_id: &str, status: i32) -> Option<Vec<TrackerStep>> {
+        let mut con = self.connection()?;
+
+        let res: RedisResult<Vec<TrackerStep>> = redis::cmd("FT.SEARCH")
+            .arg(TRACKER_INDEX)
+            .arg(format!("@tracker_id:{} @status:{}", tracker_id, status))
+            .query(&mut *con);
+
+        match res {
+            Ok(mut steps) => {
+                // Text fields match on any word, ids with dashes could match other trackers
+                let status = status.to_string();
+                steps.retain(|step| step.tracker_id == tracker_id && step.status == status);
+                steps.sort_by_key(|step| step.timestamp.parse::<u128>().unwrap_or(0));
+                Some(steps)
+            }
+            Err(err) => {
+                error!("Error obtaining tracker steps: {}", err);
+                None
+            }
+        }
+    }
+}
 
-        let clone = possible_years.unwrap().clone();
-        debug!("clone: {clone:?}");
-        clone
+impl Source for WhatsappSource {
+    fn get_requestor(&self, reference: &str) -> Result<User, String> {
+        User::from_phone_number(reference)
     }
 
-    fn get_make(tracker_id: &str) -> Option<String>;
+    fn get_vin(&self, tracker_id: &str) -> Option<String> {
+        debug!("Searching vin");
+        let steps = self.find_steps(tracker_id, VIN_STATUS_ID)?;
+
+        // Fails if no field value is found
+        steps.last().map(|step| step.value.clone())
+    }
 
-    fn get_requestor(reference: &str) -> Result<User, String>;
+    fn get_make(&self, tracker_id: &str) -> Option<String> {
+        let steps = self.find_steps(tracker_id, MAKE_STATUS_ID)?;
 
-    fn get_model(tracker_id: &str) -> Option<String>;
+        let step = steps.last()?;
+        debug!("step value: {}", &step.value);
+        Some(step.value.clone())
+    }
+
+    fn get_description(&self, tracker_id: &str) -> Option<String> {
+        let steps = self.find_steps(tracker_id, DESCRIPTION_STATUS_ID)?;
+
+        let step = steps.last()?;
+        debug!("step value: {}", &step.value);
+        Some(step.value.clone())
+    }
+
+    fn get_attached_files(&self, tracker_id: &str) -> Option<String> {
+        let steps = self.find_steps(tracker_id, DESCRIPTION_STATUS_ID)?;
+
+        let step = steps.last()?;
+        debug!("step value: {}", &step.attached_files);
+        Some(step.attached_files.clone())
+    }
+
+    fn get_model(&self, tracker_id: &str) -> Option<String> {
+        let steps = self.find_steps(tracker_id, MODEL_STATUS_ID)?;
+
+        let step = steps.last()?;
+        debug!("step value: {}", &step.value);
+        Some(step.value.clone())
+    }
 }
 
+// Reads the request data sent in a web form or email submission, the reference is ignored
+// since a source is built for each submission
 #[derive(Clone)]
-pub struct WhatsappSource {}
-impl Source for WhatsappSource {
-    fn get_requestor(reference: &str) -> Result<User, String> {
-        let user = User::from_phone_number(reference);
-        user
+pub struct SubmissionSource {
+    submission: FormSubmission,
+}
+
+impl SubmissionSource {
+    pub fn new(submission: FormSubmission) -> Self {
+        SubmissionSource { submission }
     }
+}
 
-    fn get_vin(tracker_id: &str) -> Option<String> {
-        println!("Searching vin");
-        let client = create_client().expect("Redis client couldnt be created.");
-        let mut con = client.get_connection().unwrap();
+impl Source for SubmissionSource {
+    fn get_vin(&self, _reference: &str) -> Option<String> {
+        let vin = self.submission.vin.trim();
+        (!vin.is_empty()).then(|| vin.to_string())
+    }
 
-        let res: RedisResult<Value> = redis::cmd("FT.SEARCH")
-            .arg("trackerSteps")
-            .arg(format!(
-                "@tracker_id:{} @status:{}",
-                tracker_id, VIN_STATUS_ID
-            ))
-            .query(&mut con);
+    fn get_description(&self, _reference: &str) -> Option<String> {
+        self.submission.description.clone()
+    }
 
-        if res.is_err() {
-            if res
-                .as_ref()
-                .unwrap_err()
-                .to_string()
-                .contains("response was [int(0)]")
-            {
-                // No record found
-                // return Err("No records found".to_string())
-            } else {
-                // Any other error
-                // return Err(res.unwrap_err().to_string())
-            }
-        }
+    fn get_attached_files(&self, _reference: &str) -> Option<String> {
+        self.submission.attached_files.clone()
+    }
 
-        println!("Result: {:?}", res.as_ref().unwrap());
+    fn get_make(&self, _reference: &str) -> Option<String> {
+        self.submission.make.clone()
+    }
 
-        // Find 'value' field on search
-        let mut vin_value: Option<String> = None;
-        for x in res.unwrap().as_sequence().unwrap() {
-            if let Bulk(register) = x {
-                let mut param_name = "".to_string();
-                for (index, elem) in register.iter().enumerate() {
-                    let string_val = match elem {
-                        Value::Data(val) => String::from_utf8(val.clone()),
-                        _ => {
-                            panic!("Unexpected value")
-                        }
-                    }
-                    .unwrap();
-
-                    if index % 2 == 0 {
-                        param_name = string_val;
-                    } else {
-                        if param_name == "value" {
-                            println!("Found value param");
-                            println!("{}", string_val);
-                            vin_value = Some(string_val);
-                            break;
-                        };
-                    }
-                }
-            }
+    fn get_requestor(&self, phone_number: &str) -> Result<User, String> {
+        User::from_phone_number(phone_number)
+    }
+
+    fn get_model(&self, _reference: &str) -> Option<String> {
+        self.submission.model.clone()
+    }
+}
+
+// Channels that create part requests, by the origin_system id of their notifications
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OriginSystem {
+    Whatsapp,
+    WebForm,
+    Email,
+}
+
+impl OriginSystem {
+    pub fn from_id(id: u16) -> Option<Self> {
+        match id {
+            3 => Some(OriginSystem::Whatsapp),
+            4 => Some(OriginSystem::WebForm),
+            5 => Some(OriginSystem::Email),
+            _ => None,
         }
+    }
 
-        // Fails if no field value is found, or value is empty
-        vin_value
+    // Origin stored on the requests, references are unique per origin
+    pub fn origin(&self) -> &'static str {
+        match self {
+            OriginSystem::Whatsapp => "WHATSAPP",
+            OriginSystem::WebForm => "WEB_FORM",
+            OriginSystem::Email => "EMAIL",
+        }
     }
+}
 
-    fn get_make(tracker_id: &str) -> Option<String> {
-        let client = create_client().expect("Redis client couldnt be created.");
-        let mut con = client.get_connection().unwrap();
+// Source picked for an origin system
+#[derive(Clone)]
+pub enum RegisteredSource {
+    Whatsapp(WhatsappSource),
+    Submission(SubmissionSource),
+}
 
-        let res: RedisResult<Value> = redis::cmd("FT.SEARCH")
-            .arg("trackerSteps")
-            .arg(format!(
-                "@tracker_id:{} @status:{}",
-                tracker_id, MAKE_STATUS_ID
-            ))
-            .query(&mut con);
+impl Source for RegisteredSource {
+    fn get_vin(&self, reference: &str) -> Option<String> {
+        match self {
+            RegisteredSource::Whatsapp(source) => source.get_vin(reference),
+            RegisteredSource::Submission(source) => source.get_vin(reference),
+        }
+    }
 
-        debug!("{:?}", res.as_ref().unwrap());
+    fn get_description(&self, reference: &str) -> Option<String> {
+        match self {
+            RegisteredSource::Whatsapp(source) => source.get_description(reference),
+            RegisteredSource::Submission(source) => source.get_description(reference),
+        }
+    }
 
-        let mut make: Option<String> = None;
-        for val in res.unwrap().as_sequence().unwrap() {
-            if let Bulk(register) = val {
-                let step = TrackerStep::default().parse_from_redis(register);
+    fn get_attached_files(&self, reference: &str) -> Option<String> {
+        match self {
+            RegisteredSource::Whatsapp(source) => source.get_attached_files(reference),
+            RegisteredSource::Submission(source) => source.get_attached_files(reference),
+        }
+    }
 
-                debug!("step value: {}", &step.value);
-                make = Some(step.value);
-            }
+    fn get_make(&self, reference: &str) -> Option<String> {
+        match self {
+            RegisteredSource::Whatsapp(source) => source.get_make(reference),
+            RegisteredSource::Submission(source) => source.get_make(reference),
         }
-        make
     }
 
-    fn get_description(tracker_id: &str) -> Option<String> {
-        let client = create_client().expect("Redis client couldnt be created.");
-        let mut con = client.get_connection().unwrap();
+    fn get_requestor(&self, reference: &str) -> Result<User, String> {
+        match self {
+            RegisteredSource::Whatsapp(source) => source.get_requestor(reference),
+            RegisteredSource::Submission(source) => source.get_requestor(reference),
+        }
+    }
 
-        let res: RedisResult<Value> = redis::cmd("FT.SEARCH")
-            .arg("trackerSteps")
-            .arg(format!(
-                "@tracker_id:{} @status:{}",
-                tracker_id, DESCRIPTION_STATUS_ID
-            ))
-            .query(&mut con);
+    fn get_model(&self, reference: &str) -> Option<String> {
+        match self {
+            RegisteredSource::Whatsapp(source) => source.get_model(reference),
+            RegisteredSource::Submission(source) => source.get_model(reference),
+        }
+    }
+}
 
-        debug!("{:?}", res.as_ref().unwrap());
+// Sources of every origin system. Whatsapp requests are read from the tracker steps, web
+// forms and emails carry their data in the submission
+#[derive(Clone)]
+pub struct SourceRegistry {
+    whatsapp: WhatsappSource,
+}
 
-        let mut description: Option<String> = None;
-        for val in res.unwrap().as_sequence().unwrap() {
-            if let Bulk(register) = val {
-                let step = TrackerStep::default().parse_from_redis(register);
+impl SourceRegistry {
+    pub fn new(pool: RedisPool) -> Self {
+        SourceRegistry {
+            whatsapp: WhatsappSource::new(pool),
+        }
+    }
 
-                debug!("step value: {}", &step.value);
-                description = Some(step.value);
+    // None if the origin system reads its requests from a submission and none was sent, or
+    // if one was sent to a system that doesn't
+    pub fn source(
+        &self,
+        system: OriginSystem,
+        submission: Option<FormSubmission>,
+    ) -> Option<RegisteredSource> {
+        match system {
+            OriginSystem::Whatsapp if submission.is_none() => {
+                Some(RegisteredSource::Whatsapp(self.whatsapp.clone()))
+            }
+            OriginSystem::Whatsapp => None,
+            OriginSystem::WebForm | OriginSystem::Email => {
+                submission.map(|s| RegisteredSource::Submission(SubmissionSource::new(s)))
             }
         }
-        description
     }
+}
 
-    fn get_attached_files(tracker_id: &str) -> Option<String> {
-        let client = create_client().expect("Redis client couldnt be created.");
-        let mut con = client.get_connection().unwrap();
+#[cfg(test)]
+pub mod fixtures {
+    use crate::request_structs::FormSubmission;
+    use crate::structs::SourceRegistry;
+
+    // Whatsapp steps aren't read through it, the pool never connects
+    pub fn registry() -> SourceRegistry {
+        let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+        SourceRegistry::new(r2d2::Pool::builder().build_unchecked(client))
+    }
+
+    pub fn submission(origin_system: &str) -> FormSubmission {
+        FormSubmission {
+            origin_system: String::from(origin_system),
+            reference: String::from("form-77"),
+            phone_number: String::from("+56911111111"),
+            vin: String::from("1HGCM82633A004352"),
+            make: Some(String::from("Honda")),
+            description: Some(String::from("Radiador")),
+            ..FormSubmission::default()
+        }
+    }
+}
+
+// Decoding of redis hashes, either HGETALL replies or FT.SEARCH documents
+pub mod decode {
+    use log::{debug, error};
+    use redis::{ErrorKind, FromRedisValue, RedisError, RedisResult, Value};
+    use std::collections::HashMap;
+    use std::fmt;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum DecodeError {
+        // Reply is not a list of field/value pairs
+        InvalidReply {
+            target: &'static str,
+            detail: String,
+        },
+        // Field present with a value the struct doesn't accept
+        InvalidValue {
+            target: &'static str,
+            field: &'static str,
+            detail: String,
+        },
+        // Required fields absent, fields not used by the struct are listed to spot renames
+        MissingFields {
+            target: &'static str,
+            missing: Vec<String>,
+            unexpected: Vec<String>,
+        },
+    }
+
+    impl fmt::Display for DecodeError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                DecodeError::InvalidReply { target, detail } => {
+                    write!(f, "{} couldn't be decoded: {}", target, detail)
+                }
+                DecodeError::InvalidValue {
+                    target,
+                    field,
+                    detail,
+                } => write!(
+                    f,
+                    "{} couldn't be decoded: field {}: {}",
+                    target, field, detail
+                ),
+                DecodeError::MissingFields {
+                    target,
+                    missing,
+                    unexpected,
+                } => write!(
+                    f,
+                    "{} couldn't be decoded: missing fields [{}], unexpected fields [{}]",
+                    target,
+                    missing.join(", "),
+                    unexpected.join(", ")
+                ),
+            }
+        }
+    }
 
-        let res: RedisResult<Value> = redis::cmd("FT.SEARCH")
-            .arg("trackerSteps")
-            .arg(format!(
-                "@tracker_id:{} @status:{}",
-                tracker_id, DESCRIPTION_STATUS_ID
+    impl From<DecodeError> for RedisError {
+        fn from(err: DecodeError) -> Self {
+            RedisError::from((
+                ErrorKind::TypeError,
+                "Response couldn't be decoded",
+                err.to_string(),
             ))
-            .query(&mut con);
+        }
+    }
+
+    // Field/value pairs of a hash, fields are taken out as the struct is filled
+    pub struct HashFields {
+        target: &'static str,
+        fields: HashMap<String, String>,
+        missing: Vec<String>,
+    }
+
+    impl HashFields {
+        pub fn parse(target: &'static str, v: &Value) -> Result<HashFields, DecodeError> {
+            let invalid = |detail: String| DecodeError::InvalidReply { target, detail };
 
-        debug!("{:?}", res.as_ref().unwrap());
+            let register = match v {
+                Value::Bulk(register) => register,
+                _ => return Err(invalid(format!("expected a list of fields, got {:?}", v))),
+            };
 
-        let mut attached_files: Option<String> = None;
-        for val in res.unwrap().as_sequence().unwrap() {
-            if let Bulk(register) = val {
-                let step = TrackerStep::default().parse_from_redis(register);
+            if register.len() % 2 != 0 {
+                return Err(invalid(format!(
+                    "expected field/value pairs, got {} elements",
+                    register.len()
+                )));
+            }
 
-                debug!("step value: {}", &step.attached_files);
-                attached_files = Some(step.attached_files);
+            let mut fields: HashMap<String, String> = HashMap::new();
+            for pair in register.chunks(2) {
+                let name = to_string(&pair[0]).map_err(&invalid)?;
+                let value = to_string(&pair[1])
+                    .map_err(|detail| invalid(format!("field '{}': {}", name, detail)))?;
+                fields.insert(name, value);
+            }
+
+            Ok(HashFields {
+                target,
+                fields,
+                missing: vec![],
+            })
+        }
+
+        // Missing fields are collected and reported together by finish
+        pub fn required(&mut self, name: &str) -> String {
+            match self.fields.remove(name) {
+                Some(value) => value,
+                None => {
+                    self.missing.push(name.to_string());
+                    String::new()
+                }
             }
         }
-        attached_files
-    }
 
-    fn get_model(tracker_id: &str) -> Option<String> {
-        let client = create_client().expect("Redis client couldnt be created.");
-        let mut con = client.get_connection().unwrap();
+        pub fn optional(&mut self, name: &str) -> Option<String> {
+            self.fields.remove(name)
+        }
 
-        let res: RedisResult<Value> = redis::cmd("FT.SEARCH")
-            .arg("trackerSteps")
-            .arg(format!(
-                "@tracker_id:{} @status:{}",
-                tracker_id, MAKE_STATUS_ID
-            ))
-            .query(&mut con);
+        // Fields left are ignored so newer writers can add them without breaking readers
+        pub fn finish(self) -> Result<(), DecodeError> {
+            let mut unexpected: Vec<String> = self.fields.into_keys().collect();
+            unexpected.sort();
+
+            if !self.missing.is_empty() {
+                return Err(DecodeError::MissingFields {
+                    target: self.target,
+                    missing: self.missing,
+                    unexpected,
+                });
+            }
 
-        debug!("{:?}", res.as_ref().unwrap());
+            if !unexpected.is_empty() {
+                debug!("Ignoring unknown {} fields: {:?}", self.target, unexpected);
+            }
 
-        let mut model: Option<String> = None;
-        for val in res.unwrap().as_sequence().unwrap() {
-            if let Bulk(register) = val {
-                let step = TrackerStep::default().parse_from_redis(register);
+            Ok(())
+        }
+    }
 
-                debug!("step value: {}", &step.value);
-                model = Some(step.value);
+    // Documents of an FT.SEARCH reply, document count and keys are skipped
+    pub fn documents<T: FromRedisValue>(items: &[Value]) -> RedisResult<Vec<T>> {
+        let mut parsed_values: Vec<T> = vec![];
+
+        for item in items {
+            if let Value::Bulk(_) = item {
+                match T::from_redis_value(item) {
+                    Ok(value) => parsed_values.push(value),
+                    Err(err) => {
+                        error!("Value couldn't be parsed: {}", err);
+                        return Err(err);
+                    }
+                }
             }
         }
-        model
+
+        Ok(parsed_values)
+    }
+
+    fn to_string(v: &Value) -> Result<String, String> {
+        match v {
+            Value::Data(val) => {
+                String::from_utf8(val.clone()).map_err(|err| format!("invalid utf-8: {}", err))
+            }
+            Value::Status(val) => Ok(val.clone()),
+            Value::Int(val) => Ok(val.to_string()),
+            _ => Err(format!("unexpected value {:?}", v)),
+        }
     }
 }
 
 pub mod classification {
-    use crate::helpers::print_type_name;
-    use crate::redis::classification::{get_label, get_request_labels};
-    use crate::redis::common::key_exists;
-    use log::{debug, error};
-    use redis::{from_redis_value, FromRedisValue, RedisResult};
-    use redis::{RedisError, Value};
+    use crate::structs::decode::{documents, HashFields};
+    use log::debug;
+    use redis::{FromRedisValue, RedisResult, Value};
     use serde::{Deserialize, Serialize};
-    use std::collections::{HashMap, HashSet};
-    use std::io::ErrorKind;
 
     #[derive(Debug, Serialize, Deserialize, Clone)]
     pub struct Label {
@@ -342,6 +523,7 @@ pub mod classification {
     }
 
     impl Label {
+        #[cfg(test)]
         pub fn new(id: &str, name: &str, parent: &str) -> Label {
             Label {
                 id: String::from(id),
@@ -349,274 +531,254 @@ pub mod classification {
                 parent: String::from(parent),
             }
         }
+    }
 
-        pub fn exists(id: &str) -> bool {
-            let key = format!("part-label:{}", id);
-            key_exists(&key)
-        }
+    // Label with its descendants, as returned by the label tree
+    #[derive(Debug, Serialize, Clone)]
+    pub struct LabelNode {
+        pub id: String,
+        pub name: String,
+        pub parent: String,
+        // Names from the base label down to this one
+        pub path: Vec<String>,
+        pub child_count: usize,
+        pub children: Vec<LabelNode>,
+    }
 
-        pub fn get(id: &str) -> Result<Self, String> {
-            get_label(id)
-        }
+    // Changes a taxonomy import makes, applied is false on dry runs
+    #[derive(Debug, Serialize, Clone, Default)]
+    pub struct LabelDiff {
+        pub applied: bool,
+        pub added: Vec<Label>,
+        pub renamed: Vec<LabelChange>,
+        pub reparented: Vec<LabelChange>,
+        pub deleted: Vec<Label>,
     }
 
-    impl FromRedisValue for Label {
-        fn from_redis_values(items: &[Value]) -> redis::RedisResult<Vec<Self>> {
-            debug!("Value type received: {:?}", items);
-            let mut parsed_values: Vec<Self> = vec![];
-
-            for item in items {
-                if let Value::Bulk(val) = item {
-                    debug!("item: {:?}", &item);
-                    debug!("val: {:?}", &val);
-                    let value: RedisResult<Label> = from_redis_value(item);
-
-                    if value.is_err() {
-                        error!("{}", value.as_ref().unwrap_err());
-                        return Err(RedisError::from(std::io::Error::new(
-                            ErrorKind::Other,
-                            "Value couldn't be parsed",
-                        )));
-                    }
+    #[derive(Debug, Serialize, Clone, PartialEq)]
+    pub struct LabelChange {
+        pub id: String,
+        pub from: String,
+        pub to: String,
+    }
 
-                    parsed_values.push(value.unwrap())
-                } else {
-                    // TODO: implement logging for library
-                }
+    impl LabelChange {
+        pub fn new(id: &str, from: &str, to: &str) -> LabelChange {
+            LabelChange {
+                id: String::from(id),
+                from: String::from(from),
+                to: String::from(to),
             }
+        }
+    }
 
-            if parsed_values.is_empty() {
-                return Err(RedisError::from(std::io::Error::new(
-                    ErrorKind::Other,
-                    "No values to parse",
-                )));
-            }
+    // Label assigned to a request. Leaf labels were chosen by the classifier, the rest were
+    // assigned as their ancestors
+    #[derive(Debug, Serialize, Clone)]
+    pub struct AssignedLabel {
+        pub id: String,
+        pub name: String,
+        pub parent: String,
+        // Names from the base label, e.g. "Engine > Cooling > Radiator"
+        pub path: String,
+        pub leaf: bool,
+    }
 
-            Ok(parsed_values)
-        }
-
-        fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Self> {
-            debug!("v: {v:?}");
-            if let Value::Bulk(register) = v {
-                let mut fields: HashMap<String, String> = HashMap::new();
-                let mut verification_set: HashSet<String> = HashSet::new();
-
-                // Parse bulk into key-val hashmap
-                let mut param_name = "".to_string();
-                for (index, elem) in register.iter().enumerate() {
-                    debug!("elem: {:?}", elem);
-                    let string_val = match elem {
-                        Value::Data(val) => String::from_utf8(val.clone()),
-                        _ => {
-                            let err = format!(
-                                "Unexpected Value type received: {}",
-                                print_type_name(&elem)
-                            );
-                            return Err(RedisError::from(std::io::Error::new(
-                                ErrorKind::Other,
-                                err,
-                            )));
-                        }
-                    }
-                    .unwrap();
+    impl FromRedisValue for Label {
+        fn from_redis_values(items: &[Value]) -> RedisResult<Vec<Self>> {
+            debug!("Value type received: {:?}", items);
+            documents(items)
+        }
 
-                    if index % 2 == 0 {
-                        param_name = string_val;
-                        verification_set.insert(param_name.clone());
-                    } else {
-                        fields.insert(param_name.to_string(), string_val);
-                    }
-                }
+        fn from_redis_value(v: &Value) -> RedisResult<Self> {
+            let mut fields = HashFields::parse("Label", v)?;
 
-                debug!("fields: {:?}", fields);
-
-                let mut label = Label::new("", "", "");
-                // Verifiy set is empty
-                label.id = fields.get("id").expect("Id field not found").to_string();
-                verification_set.remove("id");
-
-                label.name = fields
-                    .get("name")
-                    .expect("name field not found")
-                    .to_string();
-                verification_set.remove("name");
-
-                label.parent = fields
-                    .get("parent")
-                    .expect("last_name field not found")
-                    .to_string();
-                verification_set.remove("parent");
-
-                if !verification_set.is_empty() {
-                    return Err(RedisError::from(std::io::Error::new(
-                        ErrorKind::Other,
-                        "Aditional unexpected values found",
-                    )));
-                }
+            let label = Label {
+                id: fields.required("id"),
+                name: fields.required("name"),
+                parent: fields.required("parent"),
+            };
+            fields.finish()?;
 
-                debug!("Parsed label: {:?}", label);
-                return Ok(label);
-            } else {
-                return Err(RedisError::from(std::io::Error::new(
-                    ErrorKind::Other,
-                    "Unexpected redis value type",
-                )));
-            }
+            debug!("Parsed label: {:?}", label);
+            Ok(label)
         }
     }
 }
 
 pub mod part_request {
-    use crate::redis::classification::get_request_labels;
-    use crate::redis::common::key_exists;
-    use crate::redis::part_register::{
-        set_request_details, set_request_requestor, set_request_vehicle_information, get_request_by_id, get_request_vehicle
-    };
-    use crate::structs::classification::Label;
+    use crate::structs::classification::AssignedLabel;
+    use crate::structs::decode::{documents, DecodeError, HashFields};
     use crate::structs::Source;
+    use crate::vin::{self, VinError, VinInformation};
     use fizzy_commons::shared_structs::user_management::User;
     use log::{debug, error};
     use redis::FromRedisValue;
     use redis::RedisResult;
-    use redis::{RedisError, Value};
-    use serde::Serialize;
-    use std::collections::{HashMap, HashSet};
-    use std::io::ErrorKind;
+    use redis::Value;
+    use serde::{Deserialize, Serialize};
+    use std::fmt;
+    use std::time::{SystemTime, UNIX_EPOCH};
 
-    #[derive(Debug, Serialize, Clone)]
+    #[derive(Debug, Serialize, Clone, Default)]
     pub struct VehicleData {
-        make: Option<String>,
-        model: Option<String>,
-        vin: Option<String>,
-        year: Option<String>,
+        pub make: Option<String>,
+        pub model: Option<String>,
+        pub vin: Option<String>,
+        // Comma separated candidates until one is inferred or selected
+        pub year: Option<String>,
+        pub year_source: Option<YearSource>,
+        // Make and model the VIN stands for according to the catalog
+        pub vin_make: Option<String>,
+        pub vin_model: Option<String>,
+        // Comma separated fields the customer gave that disagree with the VIN
+        pub mismatch: Option<String>,
     }
 
     impl FromRedisValue for VehicleData {
-        fn from_redis_values(items: &[redis::Value]) -> redis::RedisResult<Vec<Self>> {
-            let mut parsed_values: Vec<Self> = vec![];
-
-            for item in items {
-                if let Value::Bulk(val) = item {
-                    let value: RedisResult<Self> =
-                        Self::from_redis_value(&Value::Bulk(vec![item.clone()]));
-
-                    if value.is_err() {
-                        error!(
-                            "Value couldn't be parsed: {}",
-                            value.as_ref().unwrap_err().to_string()
-                        );
-                        return Err(RedisError::from(std::io::Error::new(
-                            ErrorKind::Other,
-                            format!(
-                                "Value couldn't be parsed: {}",
-                                value.as_ref().unwrap_err().to_string()
-                            ),
-                        )));
-                    }
+        fn from_redis_values(items: &[Value]) -> RedisResult<Vec<Self>> {
+            documents(items)
+        }
 
-                    parsed_values.push(value.unwrap())
-                } else {
-                    // TODO: implement logging for library
-                }
+        // Every field is optional, year is set later by the classifier
+        fn from_redis_value(v: &Value) -> RedisResult<Self> {
+            let mut fields = HashFields::parse("VehicleData", v)?;
+
+            let data = VehicleData {
+                make: fields.optional("make"),
+                model: fields.optional("model"),
+                vin: fields.optional("vin"),
+                year: fields.optional("year"),
+                year_source: fields
+                    .optional("year_source")
+                    .and_then(|source| YearSource::parse(&source)),
+                vin_make: fields.optional("vin_make"),
+                vin_model: fields.optional("vin_model"),
+                mismatch: fields.optional("mismatch"),
+            };
+            fields.finish()?;
+
+            Ok(data)
+        }
+    }
+
+    impl VehicleData {
+        #[cfg(test)]
+        pub fn new(make: &str, model: &str, year: &str, vin: &str) -> Self {
+            VehicleData {
+                make: Some(String::from(make)),
+                model: Some(String::from(model)),
+                vin: Some(String::from(vin)),
+                year: Some(String::from(year)),
+                ..VehicleData::default()
             }
+        }
 
-            Ok(parsed_values)
-        }
-        fn from_redis_value(v: &redis::Value) -> RedisResult<Self> {
-            if let Value::Bulk(register) = v {
-                let mut fields: HashMap<String, String> = HashMap::new();
-                let mut verification_set: HashSet<String> = HashSet::new();
-
-                // Parse bulk into key-val hashmap
-                let mut param_name = "".to_string();
-                for (index, elem) in register.iter().enumerate() {
-                    let string_val = match elem {
-                        Value::Data(val) => String::from_utf8(val.clone()),
-                        _ => {
-                            panic!("Unexpected value")
-                        }
-                    }
-                    .unwrap();
+        // Fields not found by the source are left out of the hash
+        pub fn get_redis_fields(&self) -> Vec<(String, String)> {
+            let year_source = self.year_source.map(|source| source.as_str().to_string());
+
+            [
+                ("make", &self.make),
+                ("model", &self.model),
+                ("year", &self.year),
+                ("year_source", &year_source),
+                ("vin", &self.vin),
+                ("vin_make", &self.vin_make),
+                ("vin_model", &self.vin_model),
+                ("mismatch", &self.mismatch),
+            ]
+            .into_iter()
+            .filter_map(|(name, value)| Some((String::from(name), value.clone()?)))
+            .collect()
+        }
 
-                    if index % 2 == 0 {
-                        param_name = string_val;
-                        verification_set.insert(param_name.clone());
-                    } else {
-                        fields.insert(param_name.to_string(), string_val);
+        // Fills make and model from the VIN when the customer didn't give them, those given that
+        // disagree with it are flagged on mismatch
+        pub fn check_vin(&mut self, information: &VinInformation) {
+            let mut mismatch = vec![];
+
+            for (name, given, inferred) in [
+                ("make", &mut self.make, &information.make),
+                ("model", &mut self.model, &information.model),
+            ] {
+                match (given.as_deref(), inferred) {
+                    (None, Some(inferred)) => *given = Some(inferred.clone()),
+                    (Some(value), Some(inferred)) if !same_name(value, inferred) => {
+                        mismatch.push(name)
                     }
+                    _ => {}
                 }
-
-                let mut data: VehicleData = VehicleData::new("", "", "", "");
-                // Verifiy set is empty
-
-                data.make = Some(fields.get("make").expect("make field not found").to_string());
-                verification_set.remove("make");
-
-                data.model = Some(fields
-                    .get("model")
-                    .expect("model field not found")
-                    .to_string());
-                verification_set.remove("model");
-
-                data.year = Some(fields
-                    .get("year")
-                    .expect("year reference field not found")
-                    .to_string());
-                verification_set.remove("year");
-
-                data.vin = Some(fields
-                    .get("vin")
-                    .expect("vin field not found")
-                    .to_string());
-                verification_set.remove("vin");
-
-                if !verification_set.is_empty() {
-                    error!("Aditional unexpected values found {:?}", verification_set);
-                    return Err(RedisError::from(std::io::Error::new(
-                        ErrorKind::Other,
-                        "Aditional unexpected values found",
-                    )));
-                }
-
-                return Ok(data);
-            }else{
-                return Err(RedisError::from(std::io::Error::new(
-                    ErrorKind::Other,
-                    "Error converting struct from redis value",
-                )));
             }
-        }
-    }
-    impl VehicleData {
 
-        pub fn new(make:&str, model: &str, year: &str, vin: &str) -> Self{
-            VehicleData { make: Some(String::from(make)), model: Some(String::from(model)), vin: Some(String::from(vin)), year: Some(String::from(year)) }
+            self.vin_make = information.make.clone();
+            self.vin_model = information.model.clone();
+            self.mismatch = (!mismatch.is_empty()).then(|| mismatch.join(","));
+        }
 
+        // Fields copied to the request hash so request-search can filter on them
+        pub fn get_index_fields(&self) -> Vec<(String, String)> {
+            [
+                ("make", &self.make),
+                ("model", &self.model),
+                ("year", &self.year),
+            ]
+            .into_iter()
+            .filter_map(|(name, value)| Some((String::from(name), value.clone()?)))
+            .collect()
         }
 
-        pub fn get_redis_fields(&self) -> Vec<(String, String)> {
-            let mut vec: Vec<(String, String)> = vec![];
+        // Fields copied to the request text document, see RequestMatch
+        pub fn get_text_fields(&self) -> Vec<(String, String)> {
+            [
+                ("vin", &self.vin),
+                ("make", &self.make),
+                ("model", &self.model),
+            ]
+            .into_iter()
+            .filter_map(|(name, value)| Some((String::from(name), value.clone()?)))
+            .collect()
+        }
+    }
 
-            vec.push((
-                String::from("make"),
-                String::from(self.make.as_ref().unwrap()),
-            ));
+    // Names compared ignoring case and punctuation, either may hold the other ("Mercedes" and
+    // "Mercedes-Benz", "Civic 1.8" and "Civic")
+    fn same_name(given: &str, inferred: &str) -> bool {
+        let normalize = |name: &str| -> String {
+            name.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect()
+        };
+        let (given, inferred) = (normalize(given), normalize(inferred));
+
+        !given.is_empty() && (given.contains(&inferred) || inferred.contains(&given))
+    }
 
-            vec.push((
-                String::from("model"),
-                String::from(self.model.as_ref().unwrap()),
-            ));
+    // How the year of a vehicle was set, stored on the `year_source` field
+    #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum YearSource {
+        // Single candidate left by the VIN decoder
+        Inferred,
+        // Selected by a classifier or given when the request was registered
+        Manual,
+    }
 
-            vec.push((
-                String::from("year"),
-                String::from(self.year.as_ref().unwrap()),
-            ));
+    impl YearSource {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                YearSource::Inferred => "INFERRED",
+                YearSource::Manual => "MANUAL",
+            }
+        }
 
-            vec.push((
-                String::from("vin"),
-                String::from(self.vin.as_ref().unwrap()),
-            ));
-            vec
+        pub fn parse(source: &str) -> Option<YearSource> {
+            match source {
+                "INFERRED" => Some(YearSource::Inferred),
+                "MANUAL" => Some(YearSource::Manual),
+                _ => None,
+            }
         }
     }
 
@@ -627,6 +789,8 @@ pub mod part_request {
         pub model: Option<String>,
         pub vin: Option<String>,
         pub year: Option<String>,
+        pub year_source: Option<YearSource>,
+        pub information: Option<VinInformation>,
         pub source: Option<T>,
     }
 
@@ -637,6 +801,8 @@ pub mod part_request {
                 model: None,
                 vin: None,
                 year: None,
+                year_source: None,
+                information: None,
                 source: None,
             }
         }
@@ -644,16 +810,29 @@ pub mod part_request {
 
     impl<T: Source> VehicleDataBuilder<T> {
         pub fn build(self) -> VehicleData {
-            VehicleData {
+            let mut vehicle = VehicleData {
                 make: self.make,
                 model: self.model,
                 vin: self.vin,
                 year: self.year,
+                year_source: self.year_source,
+                ..VehicleData::default()
+            };
+
+            if let Some(information) = &self.information {
+                vehicle.check_vin(information);
             }
+
+            vehicle
+        }
+
+        pub fn source(&mut self, source: T) -> &mut Self {
+            self.source = Some(source);
+            self
         }
 
         pub fn vin(&mut self, reference: &str) -> &mut Self {
-            let vin = T::get_vin(reference);
+            let vin = self.get_source().get_vin(reference);
             if vin.is_none() {
                 error!("Vin wasnt found");
             }
@@ -662,7 +841,7 @@ pub mod part_request {
         }
 
         pub fn make(&mut self, tracker_id: &str) -> &mut Self {
-            let make = T::get_make(tracker_id);
+            let make = self.get_source().get_make(tracker_id);
             if make.is_none() {
                 error!("make wasnt found");
             }
@@ -671,7 +850,7 @@ pub mod part_request {
         }
 
         pub fn model(&mut self, tracker_id: &str) -> &mut Self {
-            let model = T::get_model(tracker_id);
+            let model = self.get_source().get_model(tracker_id);
             if model.is_none() {
                 error!("model wasnt found");
             }
@@ -679,28 +858,299 @@ pub mod part_request {
             self
         }
 
-        pub fn year(&mut self) -> &mut Self {
-            if self.vin.is_none() {
-                panic!("Vin has to be defined before decoding it");
+        // Candidate years of the VIN, inferred if only one is left. Fails if the VIN is missing
+        // or malformed
+        pub fn year(&mut self) -> Result<&mut Self, VinError> {
+            let information = vin::decode(self.vin.as_deref().unwrap_or_default())?;
+            debug!("VIN information: {:?}", information);
+
+            self.year = Some(information.years.join(","));
+            self.year_source = information.year.as_ref().map(|_| YearSource::Inferred);
+            self.vin = Some(information.vin.clone());
+            self.information = Some(information);
+
+            Ok(self)
+        }
+
+        fn get_source(&self) -> &T {
+            self.source
+                .as_ref()
+                .expect("Source has to be defined before querying it")
+        }
+    }
+
+    // Lifecycle of a part request, stored on the `classified` field
+    #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum RequestStatus {
+        Pending,
+        InReview,
+        Classified,
+        Rejected,
+        NeedsInfo,
+        Quoted,
+    }
+
+    impl RequestStatus {
+        pub const ALL: [RequestStatus; 6] = [
+            RequestStatus::Pending,
+            RequestStatus::InReview,
+            RequestStatus::Classified,
+            RequestStatus::Rejected,
+            RequestStatus::NeedsInfo,
+            RequestStatus::Quoted,
+        ];
+
+        // Statuses in which labels and year can still be changed
+        pub const EDITABLE: [RequestStatus; 2] = [RequestStatus::Pending, RequestStatus::InReview];
+
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                RequestStatus::Pending => "PENDING",
+                RequestStatus::InReview => "IN_REVIEW",
+                RequestStatus::Classified => "CLASSIFIED",
+                RequestStatus::Rejected => "REJECTED",
+                RequestStatus::NeedsInfo => "NEEDS_INFO",
+                RequestStatus::Quoted => "QUOTED",
             }
-            let possible_years = T::decode_year(&self.vin.as_ref().unwrap());
-            self.year = Some(format!("{},{}", possible_years.0, possible_years.1));
+        }
 
-            self
+        pub fn parse(status: &str) -> Option<RequestStatus> {
+            match status {
+                // Requests completed before the lifecycle existed
+                "DONE" => Some(RequestStatus::Classified),
+                _ => RequestStatus::ALL
+                    .into_iter()
+                    .find(|candidate| candidate.as_str() == status),
+            }
+        }
+
+        // Every allowed move between statuses
+        pub fn next_statuses(&self) -> &'static [RequestStatus] {
+            use RequestStatus::*;
+
+            match self {
+                Pending => &[InReview, Classified, NeedsInfo, Rejected],
+                InReview => &[Pending, Classified, NeedsInfo, Rejected],
+                NeedsInfo => &[Pending, InReview, Rejected],
+                Classified => &[InReview, Quoted],
+                Rejected => &[Pending],
+                Quoted => &[],
+            }
+        }
+
+        pub fn can_transition_to(&self, next: RequestStatus) -> bool {
+            self.next_statuses().contains(&next)
+        }
+    }
+
+    impl fmt::Display for RequestStatus {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.as_str())
+        }
+    }
+
+    // Status change applied to a request
+    #[derive(Debug, Serialize, Clone, PartialEq)]
+    pub struct StatusTransition {
+        pub request_id: String,
+        pub from: RequestStatus,
+        pub to: RequestStatus,
+    }
+
+    // Lease of a request held by a classifier, kept on `part-request:{id}:claim` until it
+    // expires or is released
+    #[derive(Debug, Serialize, Clone, PartialEq)]
+    pub struct Claim {
+        pub request_id: String,
+        pub owner: String,
+        pub expires_at: String,
+    }
+
+    impl Claim {
+        pub fn new(request_id: &str, owner: &str, ttl_ms: u64) -> Claim {
+            let now: u128 = now_millis().parse().unwrap_or_default();
+
+            Claim {
+                request_id: request_id.to_string(),
+                owner: owner.to_string(),
+                expires_at: (now + ttl_ms as u128).to_string(),
+            }
+        }
+    }
+
+    // Request handed out by the queue along with its claim
+    #[derive(Debug, Serialize, Clone)]
+    pub struct ClaimedRequest {
+        pub claim: Claim,
+        pub request: PartRequest,
+    }
+
+    // Page of a request search, total counts every match and not only the returned ones
+    #[derive(Debug, Serialize, Clone)]
+    pub struct RequestPage {
+        pub total: usize,
+        pub offset: usize,
+        pub limit: usize,
+        pub requests: Vec<PartRequest>,
+    }
+
+    // Request found by text search on `part-request-text:{id}`, the words matched are wrapped in
+    // <em> tags. Labels hold the ids assigned to the request
+    #[derive(Debug, Serialize, Clone, PartialEq)]
+    pub struct RequestMatch {
+        pub request_id: String,
+        pub description: Option<String>,
+        pub vin: Option<String>,
+        pub make: Option<String>,
+        pub model: Option<String>,
+        pub labels: Vec<String>,
+    }
+
+    impl FromRedisValue for RequestMatch {
+        fn from_redis_values(items: &[Value]) -> RedisResult<Vec<Self>> {
+            documents(items)
+        }
+
+        fn from_redis_value(v: &Value) -> RedisResult<Self> {
+            let mut fields = HashFields::parse("RequestMatch", v)?;
+
+            let request_match = RequestMatch {
+                request_id: fields.required("request_id"),
+                description: fields.optional("description"),
+                vin: fields.optional("vin"),
+                make: fields.optional("make"),
+                model: fields.optional("model"),
+                labels: vec![],
+            };
+            fields.finish()?;
+
+            Ok(request_match)
+        }
+    }
+
+    // Page of a text search, best matches first
+    #[derive(Debug, Serialize, Clone)]
+    pub struct RequestMatches {
+        pub total: usize,
+        pub offset: usize,
+        pub limit: usize,
+        pub matches: Vec<RequestMatch>,
+    }
+
+    // Kind of change recorded on the request events stream
+    #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    pub enum RequestAction {
+        LabelAdded,
+        LabelRemoved,
+        YearSelected,
+        StatusChanged,
+    }
+
+    impl RequestAction {
+        pub const ALL: [RequestAction; 4] = [
+            RequestAction::LabelAdded,
+            RequestAction::LabelRemoved,
+            RequestAction::YearSelected,
+            RequestAction::StatusChanged,
+        ];
+
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                RequestAction::LabelAdded => "LABEL_ADDED",
+                RequestAction::LabelRemoved => "LABEL_REMOVED",
+                RequestAction::YearSelected => "YEAR_SELECTED",
+                RequestAction::StatusChanged => "STATUS_CHANGED",
+            }
+        }
+
+        pub fn parse(action: &str) -> Option<RequestAction> {
+            RequestAction::ALL
+                .into_iter()
+                .find(|candidate| candidate.as_str() == action)
+        }
+    }
+
+    // Entry of `part-request:{id}:events`. Label changes keep the assigned label ids sorted and
+    // comma separated, empty values mean nothing was set
+    #[derive(Debug, Serialize, Clone, PartialEq)]
+    pub struct RequestEvent {
+        pub id: String,
+        pub actor: String,
+        pub timestamp: String,
+        pub action: RequestAction,
+        pub before: String,
+        pub after: String,
+    }
+
+    impl FromRedisValue for RequestEvent {
+        // XRANGE entries come as [entry id, [field, value, ...]]
+        fn from_redis_value(v: &Value) -> RedisResult<Self> {
+            let (id, values) = match v {
+                Value::Bulk(entry) if entry.len() == 2 => (&entry[0], &entry[1]),
+                _ => {
+                    return Err(DecodeError::InvalidReply {
+                        target: "RequestEvent",
+                        detail: format!("expected a stream entry, got {:?}", v),
+                    }
+                    .into())
+                }
+            };
+
+            let id: String = FromRedisValue::from_redis_value(id)?;
+            let mut fields = HashFields::parse("RequestEvent", values)?;
+            let actor = fields.required("actor");
+            let timestamp = fields.required("timestamp");
+            let action = fields.required("action");
+            let before = fields.optional("before").unwrap_or_default();
+            let after = fields.optional("after").unwrap_or_default();
+            fields.finish()?;
+
+            let action = match RequestAction::parse(&action) {
+                Some(action) => action,
+                None => {
+                    return Err(DecodeError::InvalidValue {
+                        target: "RequestEvent",
+                        field: "action",
+                        detail: format!("unknown action '{}'", action),
+                    }
+                    .into())
+                }
+            };
+
+            Ok(RequestEvent {
+                id,
+                actor,
+                timestamp,
+                action,
+                before,
+                after,
+            })
+        }
+    }
+
+    // Milliseconds since the epoch, used for request and event timestamps
+    pub fn now_millis() -> String {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(n) => n.as_millis().to_string(),
+            Err(_) => panic!("SystemTime before UNIX EPOCH!"),
         }
     }
 
     // PART REQUEST
-    #[derive(Debug, Serialize)]
+    #[derive(Debug, Serialize, Clone)]
     pub struct PartRequest {
         pub id: String,
         pub origin: String,
         pub origin_reference: String,
         pub timestamp: String,
-        pub classified: String,
+        pub classified: RequestStatus,
         pub vehicle: Option<VehicleData>,
         pub requestor: Option<Requestor>,
         pub details: Option<RequestDetails>,
+        pub labels: Option<Vec<AssignedLabel>>,
+        pub history: Option<Vec<RequestEvent>>,
     }
 
     impl PartRequest {
@@ -709,29 +1159,43 @@ pub mod part_request {
             origin: &str,
             origin_reference: &str,
             timestamp: &str,
-            classified: &str,
+            classified: RequestStatus,
         ) -> PartRequest {
             PartRequest {
                 id: id.to_string(),
                 origin: origin.to_string(),
                 origin_reference: origin_reference.to_string(),
                 timestamp: timestamp.to_string(),
-                classified: classified.to_string(),
+                classified,
                 vehicle: None,
                 requestor: None,
                 details: None,
+                labels: None,
+                history: None,
             }
         }
 
-        pub fn exists(id: &str) -> bool {
-            let key = format!("part-request:{}", id);
-            key_exists(&key)
+        // New PENDING request with a random id, timestamped now
+        pub fn pending(origin: &str, reference: &str) -> PartRequest {
+            let uuid = uuid::Uuid::new_v4().to_string();
+            let timestamp = now_millis();
+
+            PartRequest::new(
+                &uuid,
+                origin,
+                reference,
+                timestamp.as_str(),
+                RequestStatus::Pending,
+            )
         }
 
         pub fn get_redis_list(&self) -> Vec<(String, String)> {
             vec![
                 (String::from("id"), String::from(&self.id)),
-                (String::from("classified"), String::from(&self.classified)),
+                (
+                    String::from("classified"),
+                    String::from(self.classified.as_str()),
+                ),
                 (String::from("origin"), String::from(&self.origin)),
                 (
                     String::from("origin_reference"),
@@ -740,198 +1204,108 @@ pub mod part_request {
                 (String::from("timestamp"), String::from(&self.timestamp)),
             ]
         }
-
-        pub fn set_request_details(&mut self, request_details: RequestDetails) {
-            let data = set_request_details(&self.id, &request_details);
-            self.details = Some(request_details)
-        }
-
-        pub fn set_vehicle_data(&mut self, vehicle_data: VehicleData) {
-            let data = set_request_vehicle_information(&self.id, &vehicle_data);
-            self.vehicle = Some(vehicle_data)
-        }
-
-        pub fn set_requestor(&mut self, requestor: Requestor) {
-            let data = set_request_requestor(&self.id, &requestor);
-            self.requestor = Some(requestor)
-        }
-
-        pub fn get_labels_ids(self) -> Result<Vec<Label>, String> {
-            get_request_labels(&self.id)
-        }
-
-        pub fn get_vehicle_data(&mut self) -> Result<VehicleData, String>{
-            let data_res = get_request_vehicle(&self.id);
-
-            if data_res.is_err(){
-                return Err(data_res.unwrap_err());
-            }
-
-            let data = data_res.unwrap();
-
-            self.vehicle = Some(data.clone());
-            Ok(data)
-        }
-
-         pub fn get_request(request_id: &str) -> Result<Self, String>{
-             let part_request = get_request_by_id(&request_id);
-
-             if part_request.is_err(){
-                 let err = format!("Error obtaining part request: {}", part_request.unwrap_err().to_string());
-                 error!("{}", &err);
-                 return Err(err)
-             }
-
-             Ok(part_request.unwrap())
-
-        }
     }
 
     impl FromRedisValue for PartRequest {
-        fn from_redis_values(items: &[redis::Value]) -> redis::RedisResult<Vec<Self>> {
-            let mut parsed_values: Vec<Self> = vec![];
-
-            for item in items {
-                if let Value::Bulk(val) = item {
-                    let value: RedisResult<Self> = Self::from_redis_value(item);
-
-                    if value.is_err() {
-                        error!(
-                            "Value couldn't be parsed: {}",
-                            value.as_ref().unwrap_err().to_string()
-                        );
-                        return Err(RedisError::from(std::io::Error::new(
-                            ErrorKind::Other,
-                            format!(
-                                "Value couldn't be parsed: {}",
-                                value.as_ref().unwrap_err().to_string()
-                            ),
-                        )));
-                    }
-
-                    parsed_values.push(value.unwrap())
-                } else {
-                    // TODO: implement logging for library
-                }
-            }
-
-            Ok(parsed_values)
-        }
-        fn from_redis_value(v: &redis::Value) -> RedisResult<Self> {
-            if let Value::Bulk(register) = v {
-                let mut fields: HashMap<String, String> = HashMap::new();
-                let mut verification_set: HashSet<String> = HashSet::new();
-
-                // Parse bulk into key-val hashmap
-                let mut param_name = "".to_string();
-                for (index, elem) in register.iter().enumerate() {
-                    println!("{elem:?}");
-                    let string_val = match elem {
-                        Value::Data(val) => String::from_utf8(val.clone()),
-                        _ => {
-                            panic!("Unexpected value")
-                        }
-                    }
-                    .unwrap();
+        fn from_redis_values(items: &[Value]) -> RedisResult<Vec<Self>> {
+            documents(items)
+        }
 
-                    if index % 2 == 0 {
-                        param_name = string_val;
-                        verification_set.insert(param_name.clone());
-                    } else {
-                        fields.insert(param_name.to_string(), string_val);
+        fn from_redis_value(v: &Value) -> RedisResult<Self> {
+            let mut fields = HashFields::parse("PartRequest", v)?;
+
+            let id = fields.required("id");
+            let origin = fields.required("origin");
+            let origin_reference = fields.required("origin_reference");
+            let timestamp = fields.required("timestamp");
+            let classified = fields.required("classified");
+            fields.finish()?;
+
+            let status = match RequestStatus::parse(&classified) {
+                Some(status) => status,
+                None => {
+                    return Err(DecodeError::InvalidValue {
+                        target: "PartRequest",
+                        field: "classified",
+                        detail: format!("unknown status '{}'", classified),
                     }
+                    .into())
                 }
-
-                let mut request: PartRequest = PartRequest::new("", "", "", "", "");
-                // Verifiy set is empty
-
-                request.id = fields.get("id").expect("Id field not found").to_string();
-                verification_set.remove("id");
-
-                request.origin = fields
-                    .get("origin")
-                    .expect("origin field not found")
-                    .to_string();
-                verification_set.remove("origin");
-
-                request.origin_reference = fields
-                    .get("origin_reference")
-                    .expect("Origin reference field not found")
-                    .to_string();
-                verification_set.remove("origin_reference");
-
-                request.timestamp = fields
-                    .get("timestamp")
-                    .expect("timestamp field not found")
-                    .to_string();
-                verification_set.remove("timestamp");
-
-                request.classified = fields
-                    .get("classified")
-                    .expect("classified field not found")
-                    .to_string();
-                verification_set.remove("classified");
-
-                if !verification_set.is_empty() {
-                    error!("Aditional unexpected values found {:?}", verification_set);
-                    return Err(RedisError::from(std::io::Error::new(
-                        ErrorKind::Other,
-                        "Aditional unexpected values found",
-                    )));
-                }
-
-                return Ok(request);
-            }else{
-                return Err(RedisError::from(std::io::Error::new(
-                    ErrorKind::Other,
-                    "Error converting struct from redis value",
-                )));
-            }
+            };
+
+            Ok(PartRequest::new(
+                &id,
+                &origin,
+                &origin_reference,
+                &timestamp,
+                status,
+            ))
         }
     }
 
-    #[derive(Debug, Serialize)]
+    #[derive(Debug, Serialize, Clone)]
     pub struct RequestDetails {
         pub description: Option<String>,
         pub attached_files: Option<String>,
     }
 
     impl RequestDetails {
-        pub fn new() -> RequestDetails {
-            RequestDetails {
-                description: None,
-                attached_files: None,
+        // Fields not found by the source are left out of the hash
+        pub fn get_redis_fields(&self) -> Vec<(String, String)> {
+            [
+                ("description", &self.description),
+                ("attached_files", &self.attached_files),
+            ]
+            .into_iter()
+            .filter_map(|(name, value)| Some((String::from(name), value.clone()?)))
+            .collect()
+        }
+
+        // Fields copied to the request text document, see RequestMatch
+        pub fn get_text_fields(&self) -> Vec<(String, String)> {
+            match &self.description {
+                Some(description) => vec![(String::from("description"), description.clone())],
+                None => vec![],
             }
         }
+    }
 
-        pub fn get_redis_fields(&self) -> Vec<(String, String)> {
-            let mut vec: Vec<(String, String)> = vec![];
+    impl FromRedisValue for RequestDetails {
+        fn from_redis_value(v: &Value) -> RedisResult<Self> {
+            let mut fields = HashFields::parse("RequestDetails", v)?;
 
-            vec.push((
-                String::from("description"),
-                String::from(self.description.as_ref().unwrap()),
-            ));
-            vec.push((
-                String::from("attached_files"),
-                String::from(self.attached_files.as_ref().unwrap()),
-            ));
-            vec
+            let details = RequestDetails {
+                description: fields.optional("description"),
+                attached_files: fields.optional("attached_files"),
+            };
+            fields.finish()?;
+
+            Ok(details)
         }
     }
 
     // Requestor
-    #[derive(Debug, Serialize)]
+    #[derive(Debug, Serialize, Clone)]
     pub struct Requestor {
         pub user_id: String,
     }
 
-    impl Requestor {
-        pub fn get_redis_fields(&self) -> Vec<(String, String)> {
-            let mut vec: Vec<(String, String)> = vec![];
+    impl FromRedisValue for Requestor {
+        fn from_redis_value(v: &Value) -> RedisResult<Self> {
+            let mut fields = HashFields::parse("Requestor", v)?;
 
-            vec.push((String::from("user_id"), String::from(&self.user_id)));
+            let requestor = Requestor {
+                user_id: fields.required("user_id"),
+            };
+            fields.finish()?;
 
-            vec
+            Ok(requestor)
+        }
+    }
+
+    impl Requestor {
+        pub fn get_redis_fields(&self) -> Vec<(String, String)> {
+            vec![(String::from("user_id"), String::from(&self.user_id))]
         }
     }
 
@@ -950,13 +1324,20 @@ pub mod part_request {
     }
 
     impl<T: Source> RequestorBuilder<T> {
+        pub fn source(&mut self, source: T) -> &mut Self {
+            self.source = Some(source);
+            self
+        }
+
         pub fn requestor(&mut self, reference: &str) -> &mut Self {
-            let requestor = T::get_requestor(reference);
+            let requestor = self
+                .source
+                .as_ref()
+                .expect("Source has to be defined before querying it")
+                .get_requestor(reference)
+                .inspect_err(|err| error!("Requestor couldnt be retrieved: {}", err));
 
-            if requestor.is_err() {
-                panic!("Requestor couldnt be retrieved");
-            }
-            self.requestor = Some(requestor.unwrap());
+            self.requestor = requestor.ok();
             self
         }
 
@@ -989,8 +1370,13 @@ pub mod part_request {
     }
 
     impl<T: Source> RequestDetailsBuilder<T> {
+        pub fn source(&mut self, source: T) -> &mut Self {
+            self.source = Some(source);
+            self
+        }
+
         pub fn description(&mut self, reference: &str) -> &mut Self {
-            let desc = T::get_description(reference);
+            let desc = self.get_source().get_description(reference);
 
             if desc.is_none() {
                 error!("Description couldnt be found")
@@ -1001,7 +1387,7 @@ pub mod part_request {
         }
 
         pub fn attached_files(&mut self, reference: &str) -> &mut Self {
-            let attached_files = T::get_attached_files(reference);
+            let attached_files = self.get_source().get_attached_files(reference);
 
             if attached_files.is_none() {
                 error!("Attached files couldnt be found")
@@ -1017,6 +1403,12 @@ pub mod part_request {
                 attached_files: self.attached_files,
             }
         }
+
+        fn get_source(&self) -> &T {
+            self.source
+                .as_ref()
+                .expect("Source has to be defined before querying it")
+        }
     }
 }
 
@@ -1151,27 +1543,316 @@ pub mod constants {
     }
 }
 
+#[cfg(test)]
+mod decode_tests {
+    use crate::structs::classification::Label;
+    use crate::structs::decode::{DecodeError, HashFields};
+    use crate::structs::part_request::{
+        PartRequest, RequestAction, RequestEvent, RequestStatus, VehicleData,
+    };
+    use crate::structs::TrackerStep;
+    use redis::{FromRedisValue, Value};
+
+    fn hash(fields: &[(&str, &str)]) -> Value {
+        let mut values = vec![];
+        for (name, value) in fields {
+            values.push(Value::Data(name.as_bytes().to_vec()));
+            values.push(Value::Data(value.as_bytes().to_vec()));
+        }
+        Value::Bulk(values)
+    }
+
+    #[test]
+    fn label_decoded() {
+        let value = hash(&[("id", "3"), ("name", "Radiator"), ("parent", "2")]);
+        let label = Label::from_redis_value(&value).unwrap();
+
+        assert_eq!(label.id, "3");
+        assert_eq!(label.name, "Radiator");
+        assert_eq!(label.parent, "2");
+    }
+
+    // Passes if every missing field is reported, along with the fields that weren't expected
+    #[test]
+    fn missing_fields_listed() {
+        let value = hash(&[("id", "3"), ("label_name", "Radiator")]);
+        let mut fields = HashFields::parse("Label", &value).unwrap();
+        fields.required("id");
+        fields.required("name");
+        fields.required("parent");
+
+        let err = fields.finish().unwrap_err();
+        assert_eq!(
+            err,
+            DecodeError::MissingFields {
+                target: "Label",
+                missing: vec!["name".to_string(), "parent".to_string()],
+                unexpected: vec!["label_name".to_string()],
+            }
+        );
+
+        let res = Label::from_redis_value(&value);
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("missing fields [name, parent]"));
+    }
+
+    #[test]
+    fn unknown_fields_ignored() {
+        let value = hash(&[
+            ("id", "req-1"),
+            ("origin", "WHATSAPP"),
+            ("origin_reference", "tracker-1"),
+            ("timestamp", "1672531200000"),
+            ("classified", "PENDING"),
+            ("priority", "HIGH"),
+        ]);
+        let request = PartRequest::from_redis_value(&value).unwrap();
+
+        assert_eq!(request.id, "req-1");
+        assert_eq!(request.classified, RequestStatus::Pending);
+    }
+
+    // Passes if requests completed before the lifecycle are read as classified
+    #[test]
+    fn part_request_status() {
+        let mut fields = vec![
+            ("id", "req-1"),
+            ("origin", "WHATSAPP"),
+            ("origin_reference", "tracker-1"),
+            ("timestamp", "1672531200000"),
+            ("classified", "DONE"),
+        ];
+        let request = PartRequest::from_redis_value(&hash(&fields)).unwrap();
+        assert_eq!(request.classified, RequestStatus::Classified);
+
+        fields[4] = ("classified", "ARCHIVED");
+        assert!(PartRequest::from_redis_value(&hash(&fields)).is_err());
+    }
+
+    // Passes if statuses round trip and quoted requests can't move anymore
+    #[test]
+    fn status_transitions() {
+        for status in RequestStatus::ALL {
+            assert_eq!(RequestStatus::parse(status.as_str()), Some(status));
+        }
+
+        assert!(RequestStatus::Pending.can_transition_to(RequestStatus::InReview));
+        assert!(RequestStatus::Classified.can_transition_to(RequestStatus::Quoted));
+        assert!(!RequestStatus::Pending.can_transition_to(RequestStatus::Quoted));
+        assert!(!RequestStatus::Rejected.can_transition_to(RequestStatus::Classified));
+        assert!(RequestStatus::Quoted.next_statuses().is_empty());
+    }
+
+    // Passes if XRANGE entries are read with their stream id
+    #[test]
+    fn request_event_decoded() {
+        let fields = [
+            ("actor", "classifier-1"),
+            ("timestamp", "1672531200000"),
+            ("action", "LABEL_ADDED"),
+            ("before", ""),
+            ("after", "1,2,3"),
+        ];
+        let entry = Value::Bulk(vec![
+            Value::Data(b"1672531200000-0".to_vec()),
+            hash(&fields),
+        ]);
+        let event = RequestEvent::from_redis_value(&entry).unwrap();
+
+        assert_eq!(event.id, "1672531200000-0");
+        assert_eq!(event.action, RequestAction::LabelAdded);
+        assert_eq!(event.after, "1,2,3");
+
+        assert!(RequestEvent::from_redis_value(&hash(&fields)).is_err());
+    }
+
+    #[test]
+    fn part_request_missing_fields() {
+        let value = hash(&[("id", "req-1"), ("origin", "WHATSAPP")]);
+        let res = PartRequest::from_redis_value(&value);
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn vehicle_optional_fields() {
+        let value = hash(&[("vin", "1HGCM82633A004352"), ("make", "HONDA")]);
+        let vehicle = VehicleData::from_redis_value(&value).unwrap();
+
+        assert_eq!(vehicle.vin.as_deref(), Some("1HGCM82633A004352"));
+        assert_eq!(vehicle.make.as_deref(), Some("HONDA"));
+        assert!(vehicle.model.is_none());
+        assert!(vehicle.year.is_none());
+    }
+
+    #[test]
+    fn tracker_step_optional_fields() {
+        let value = hash(&[
+            ("tracker_id", "tracker-1"),
+            ("timestamp", "1672531200000"),
+            ("status", "7"),
+            ("value", "1HGCM82633A004352"),
+        ]);
+        let step = TrackerStep::from_redis_value(&value).unwrap();
+
+        assert_eq!(step.value, "1HGCM82633A004352");
+        assert_eq!(step.attached_files, "");
+    }
+
+    #[test]
+    fn tracker_step_missing_value() {
+        let value = hash(&[("tracker_id", "tracker-1"), ("status", "7")]);
+        let res = TrackerStep::from_redis_value(&value);
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn invalid_values_rejected() {
+        let non_utf8 = Value::Bulk(vec![
+            Value::Data(b"id".to_vec()),
+            Value::Data(vec![0xff, 0xfe]),
+        ]);
+        let nested = Value::Bulk(vec![Value::Data(b"id".to_vec()), Value::Bulk(vec![])]);
+        let odd = Value::Bulk(vec![Value::Data(b"id".to_vec())]);
+
+        assert!(Label::from_redis_value(&non_utf8).is_err());
+        assert!(Label::from_redis_value(&nested).is_err());
+        assert!(Label::from_redis_value(&odd).is_err());
+        assert!(Label::from_redis_value(&Value::Nil).is_err());
+    }
+
+    // FT.SEARCH replies: count, then key and fields of every document
+    #[test]
+    fn search_reply_decoded() {
+        let reply = Value::Bulk(vec![
+            Value::Int(2),
+            Value::Data(b"part-label:1".to_vec()),
+            hash(&[("id", "1"), ("name", "Engine"), ("parent", "0")]),
+            Value::Data(b"part-label:5".to_vec()),
+            hash(&[("id", "5"), ("name", "Brakes"), ("parent", "0")]),
+        ]);
+        let labels: Vec<Label> = FromRedisValue::from_redis_value(&reply).unwrap();
+
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[1].name, "Brakes");
+    }
+
+    #[test]
+    fn empty_search_reply() {
+        let reply = Value::Bulk(vec![Value::Int(0)]);
+        let labels: Vec<Label> = FromRedisValue::from_redis_value(&reply).unwrap();
+
+        assert!(labels.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod source_tests {
+    use crate::structs::fixtures::{registry, submission};
+    use crate::structs::{OriginSystem, RegisteredSource, Source, WhatsappSource};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    #[test]
+    fn origin_systems() {
+        assert_eq!(OriginSystem::from_id(3), Some(OriginSystem::Whatsapp));
+        assert_eq!(OriginSystem::from_id(4).unwrap().origin(), "WEB_FORM");
+        assert_eq!(OriginSystem::from_id(5).unwrap().origin(), "EMAIL");
+        assert_eq!(OriginSystem::from_id(1), None);
+    }
+
+    // Passes if each system gets its source, and only forms and emails read a submission
+    #[test]
+    fn registry_sources() {
+        let registry = registry();
+
+        let source = registry.source(OriginSystem::Whatsapp, None);
+        assert!(matches!(source, Some(RegisteredSource::Whatsapp(_))));
+        let source = registry.source(OriginSystem::Whatsapp, Some(submission("3")));
+        assert!(source.is_none());
+
+        let mut padded = submission("4");
+        padded.vin = String::from(" 1HGCM82633A004352 ");
+        let source = registry.source(OriginSystem::WebForm, Some(padded));
+        let source = source.unwrap();
+        let vin = source.get_vin("form-77");
+        assert_eq!(vin.as_deref(), Some("1HGCM82633A004352"));
+        assert_eq!(source.get_make("form-77").as_deref(), Some("Honda"));
+        assert_eq!(source.get_model("form-77"), None);
+        assert!(registry.source(OriginSystem::Email, None).is_none());
+    }
+
+    // Source connected to a server answering every command with no steps found, the commands
+    // it receives are sent back
+    fn whatsapp_source() -> (WhatsappSource, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, commands) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0; 512];
+            while let Ok(read) = stream.read(&mut buffer) {
+                if read == 0 || stream.write_all(b"*1\r\n:0\r\n").is_err() {
+                    break;
+                }
+                let _ = sender.send(String::from_utf8_lossy(&buffer[..read]).to_string());
+            }
+        });
+
+        let client = redis::Client::open(format!("redis://{}/", address)).unwrap();
+        let pool = r2d2::Pool::builder()
+            .max_size(1)
+            .test_on_check_out(false)
+            .build_unchecked(client);
+        (WhatsappSource::new(pool), commands)
+    }
+
+    // Passes if the model is read from the model step of the workflow, not the make one
+    #[test]
+    fn whatsapp_model_step() {
+        let (source, commands) = whatsapp_source();
+
+        assert_eq!(source.get_model("tracker-9"), None);
+        let query = commands.recv().unwrap();
+        assert!(query.contains("@tracker_id:tracker-9 @status:5"));
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::errors::ClassificatorError;
     use crate::redis::classification::{get_all_labels, get_label};
+    use crate::redis::pool::{create_pool, PoolConfig, RedisPool};
     use crate::structs::classification::Label;
+    use fizzy_commons::redis::client::create_client;
+
+    fn pool() -> RedisPool {
+        create_pool(create_client().unwrap(), &PoolConfig::from_env()).unwrap()
+    }
 
     // Passes if label exists
     #[test]
     pub fn get_single_label() {
-        let label = get_label("1");
+        let label = get_label(&mut pool().get().unwrap(), "1");
         assert!(label.is_ok())
     }
 
     #[test]
     pub fn get_non_existing_label() {
-        let label = get_label("243dff");
+        let label = get_label(&mut pool().get().unwrap(), "243dff");
         assert!(label.is_err())
     }
 
     #[test]
     pub fn get_labels_search() {
-        let labels: Result<Vec<Label>, String> = get_all_labels();
+        let labels: Result<Vec<Label>, ClassificatorError> =
+            get_all_labels(&mut pool().get().unwrap());
         assert!(labels.is_ok());
     }
 }
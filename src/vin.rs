@@ -0,0 +1,555 @@
+use crate::structs::constants::get_year_encodings;
+use crate::vin::catalog::catalog;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const VIN_LENGTH: usize = 17;
+
+// Positions of the check digit, year digit and plant code, starting at 0
+const CYCLE_POSITION: usize = 6;
+const CHECK_DIGIT_POSITION: usize = 8;
+const YEAR_DIGIT_POSITION: usize = 9;
+const PLANT_POSITION: usize = 10;
+
+// Characters a VIN can hold in the order the WMI country ranges use, I, O and Q are left out
+// so they aren't taken for 1 and 0
+const VIN_CHARACTERS: &str = "ABCDEFGHJKLMNPRSTUVWXYZ1234567890";
+
+// ISO 3779 weight of each position, the check digit itself weighs 0
+const CHECK_DIGIT_WEIGHTS: [u32; VIN_LENGTH] = [8, 7, 6, 5, 4, 3, 2, 10, 0, 9, 8, 7, 6, 5, 4, 3, 2];
+
+// Country of the first WMI character followed by the range of the second one
+const COUNTRIES: &[(char, char, char, &str)] = &[
+    ('A', 'A', 'H', "South Africa"),
+    ('A', 'J', 'N', "Ivory Coast"),
+    ('B', 'A', 'E', "Angola"),
+    ('B', 'F', 'K', "Kenya"),
+    ('B', 'L', 'R', "Tanzania"),
+    ('C', 'A', 'E', "Benin"),
+    ('C', 'F', 'K', "Madagascar"),
+    ('C', 'L', 'R', "Tunisia"),
+    ('D', 'A', 'E', "Egypt"),
+    ('D', 'F', 'K', "Morocco"),
+    ('D', 'L', 'R', "Zambia"),
+    ('E', 'A', 'E', "Ethiopia"),
+    ('E', 'F', 'K', "Mozambique"),
+    ('F', 'A', 'E', "Ghana"),
+    ('F', 'F', 'K', "Nigeria"),
+    ('J', 'A', '0', "Japan"),
+    ('K', 'A', 'E', "Sri Lanka"),
+    ('K', 'F', 'K', "Israel"),
+    ('K', 'L', 'R', "South Korea"),
+    ('K', 'S', '0', "Kazakhstan"),
+    ('L', 'A', '0', "China"),
+    ('M', 'A', 'E', "India"),
+    ('M', 'F', 'K', "Indonesia"),
+    ('M', 'L', 'R', "Thailand"),
+    ('M', 'S', '0', "Myanmar"),
+    ('N', 'A', 'E', "Iran"),
+    ('N', 'F', 'K', "Pakistan"),
+    ('N', 'L', 'R', "Turkey"),
+    ('P', 'A', 'E', "Philippines"),
+    ('P', 'F', 'K', "Singapore"),
+    ('P', 'L', 'R', "Malaysia"),
+    ('R', 'A', 'E', "United Arab Emirates"),
+    ('R', 'F', 'K', "Taiwan"),
+    ('R', 'L', 'R', "Vietnam"),
+    ('R', 'S', '0', "Saudi Arabia"),
+    ('S', 'A', 'M', "United Kingdom"),
+    ('S', 'N', 'T', "Germany"),
+    ('S', 'U', 'Z', "Poland"),
+    ('S', '1', '4', "Latvia"),
+    ('T', 'A', 'H', "Switzerland"),
+    ('T', 'J', 'P', "Czech Republic"),
+    ('T', 'R', 'V', "Hungary"),
+    ('T', 'W', '1', "Portugal"),
+    ('U', 'H', 'M', "Denmark"),
+    ('U', 'N', 'T', "Ireland"),
+    ('U', 'U', 'Z', "Romania"),
+    ('U', '5', '7', "Slovakia"),
+    ('V', 'A', 'E', "Austria"),
+    ('V', 'F', 'R', "France"),
+    ('V', 'S', 'W', "Spain"),
+    ('V', 'X', '2', "Serbia"),
+    ('V', '3', '5', "Croatia"),
+    ('V', '6', '0', "Estonia"),
+    ('W', 'A', '0', "Germany"),
+    ('X', 'A', 'E', "Bulgaria"),
+    ('X', 'F', 'K', "Greece"),
+    ('X', 'L', 'R', "Netherlands"),
+    ('X', 'S', 'W', "Russia"),
+    ('X', 'X', '2', "Luxembourg"),
+    ('X', '3', '0', "Russia"),
+    ('Y', 'A', 'E', "Belgium"),
+    ('Y', 'F', 'K', "Finland"),
+    ('Y', 'L', 'R', "Malta"),
+    ('Y', 'S', 'W', "Sweden"),
+    ('Y', 'X', '2', "Norway"),
+    ('Y', '3', '5', "Belarus"),
+    ('Y', '6', '0', "Ukraine"),
+    ('Z', 'A', 'R', "Italy"),
+    ('Z', 'X', '2', "Slovenia"),
+    ('Z', '3', '5', "Lithuania"),
+    ('1', 'A', '0', "United States"),
+    ('2', 'A', '0', "Canada"),
+    ('3', 'A', 'W', "Mexico"),
+    ('3', 'X', '7', "Costa Rica"),
+    ('4', 'A', '0', "United States"),
+    ('5', 'A', '0', "United States"),
+    ('6', 'A', 'W', "Australia"),
+    ('7', 'A', 'E', "New Zealand"),
+    ('8', 'A', 'E', "Argentina"),
+    ('8', 'F', 'J', "Chile"),
+    ('8', 'L', 'R', "Ecuador"),
+    ('8', 'S', 'W', "Peru"),
+    ('8', 'X', '2', "Venezuela"),
+    ('9', 'A', 'E', "Brazil"),
+    ('9', 'F', 'J', "Colombia"),
+    ('9', 'L', 'R', "Paraguay"),
+    ('9', 'S', 'W', "Uruguay"),
+    ('9', '3', '9', "Brazil"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VinInformation {
+    pub vin: String,
+    // World manufacturer identifier, the first 3 characters
+    pub wmi: String,
+    pub manufacturer: Option<String>,
+    pub country: Option<String>,
+    // Inferred from the catalog, model only when the VDS identifies one
+    pub make: Option<String>,
+    pub model: Option<String>,
+    // Model years the year digit can stand for, the year is inferred once a single one is left
+    pub years: Vec<String>,
+    pub year: Option<String>,
+    pub plant: String,
+    pub serial: String,
+    // Only required on North American VINs, others may not carry one
+    pub check_digit_valid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VinError {
+    Length(usize),
+    // Position starts at 1 as printed on the vehicle
+    InvalidCharacter { position: usize, character: char },
+    CheckDigit { expected: char, found: char },
+    YearDigit(char),
+    // Every model year the digit stands for is in the future
+    FutureYear(char),
+}
+
+impl fmt::Display for VinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VinError::Length(length) => {
+                write!(f, "VIN has {} characters, expected {}", length, VIN_LENGTH)
+            }
+            VinError::InvalidCharacter {
+                position,
+                character,
+            } => write!(
+                f,
+                "VIN character '{}' at position {} is not valid",
+                character, position
+            ),
+            VinError::CheckDigit { expected, found } => {
+                write!(f, "VIN check digit is '{}', expected '{}'", found, expected)
+            }
+            VinError::YearDigit(digit) => write!(f, "VIN year digit '{}' is not valid", digit),
+            VinError::FutureYear(digit) => {
+                write!(f, "VIN year digit '{}' stands for a future year", digit)
+            }
+        }
+    }
+}
+
+// Decodes the VIN once it's validated, lowercase letters and surrounding spaces are accepted
+pub fn decode(vin: &str) -> Result<VinInformation, VinError> {
+    decode_as_of(vin, current_year())
+}
+
+// Decodes the VIN as it would be on the given calendar year
+pub fn decode_as_of(vin: &str, current_year: u16) -> Result<VinInformation, VinError> {
+    let vin = vin.trim().to_ascii_uppercase();
+
+    let length = vin.chars().count();
+    if length != VIN_LENGTH {
+        return Err(VinError::Length(length));
+    }
+
+    let invalid = vin
+        .chars()
+        .enumerate()
+        .find(|(_, character)| !VIN_CHARACTERS.contains(*character));
+    if let Some((position, character)) = invalid {
+        return Err(VinError::InvalidCharacter {
+            position: position + 1,
+            character,
+        });
+    }
+
+    // Only ASCII characters are left
+    let digits: Vec<char> = vin.chars().collect();
+    let wmi = &vin[..3];
+
+    let expected = check_digit(&digits);
+    let found = digits[CHECK_DIGIT_POSITION];
+    if expected != found && is_north_american(wmi) {
+        return Err(VinError::CheckDigit { expected, found });
+    }
+
+    let years = model_years(&digits, is_north_american(wmi), current_year)?;
+
+    let catalog = catalog();
+    let entry = catalog.manufacturer(wmi);
+    let model = catalog.model(wmi, &vin[3..CHECK_DIGIT_POSITION]);
+
+    Ok(VinInformation {
+        wmi: wmi.to_string(),
+        manufacturer: entry.map(|entry| entry.manufacturer.clone()),
+        country: country(wmi).map(String::from),
+        make: entry.map(|entry| entry.make.clone()),
+        model: model.map(String::from),
+        year: (years.len() == 1).then(|| years[0].clone()),
+        years,
+        plant: digits[PLANT_POSITION].to_string(),
+        serial: vin[PLANT_POSITION + 1..].to_string(),
+        check_digit_valid: expected == found,
+        vin,
+    })
+}
+
+// Candidates of the year digit, one per 30 year cycle. North American VINs have an alphabetic
+// 7th character only since the 2010 cycle, and vehicles are sold at most a model year ahead
+fn model_years(
+    digits: &[char],
+    north_american: bool,
+    current_year: u16,
+) -> Result<Vec<String>, VinError> {
+    let year_digit = digits[YEAR_DIGIT_POSITION];
+    let (old, current) = get_year_encodings()
+        .remove(&year_digit.to_string())
+        .ok_or(VinError::YearDigit(year_digit))?;
+
+    let mut years = vec![old, current];
+    if north_american && digits[CYCLE_POSITION].is_ascii_alphabetic() {
+        years.remove(0);
+    }
+
+    let latest = current_year + 1;
+    years.retain(|year| year.parse::<u16>().is_ok_and(|year| year <= latest));
+    if years.is_empty() {
+        return Err(VinError::FutureYear(year_digit));
+    }
+
+    Ok(years)
+}
+
+// Gregorian year of today, from the days since 1970-01-01
+fn current_year() -> u16 {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let days = (seconds / 86_400) as i64 + 719_468;
+
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Years counted from March, January and February belong to the next one
+    let month = (5 * day_of_year + 2) / 153;
+    let year = year_of_era + era * 400 + i64::from(month >= 10);
+
+    year as u16
+}
+
+fn check_digit(digits: &[char]) -> char {
+    let sum: u32 = digits
+        .iter()
+        .zip(CHECK_DIGIT_WEIGHTS)
+        .map(|(digit, weight)| transliterate(*digit) * weight)
+        .sum();
+
+    match sum % 11 {
+        10 => 'X',
+        remainder => char::from_digit(remainder, 10).unwrap_or('0'),
+    }
+}
+
+// Numeric value of a VIN character for the check digit
+fn transliterate(digit: char) -> u32 {
+    match digit {
+        'A' | 'J' => 1,
+        'B' | 'K' | 'S' => 2,
+        'C' | 'L' | 'T' => 3,
+        'D' | 'M' | 'U' => 4,
+        'E' | 'N' | 'V' => 5,
+        'F' | 'W' => 6,
+        'G' | 'P' | 'X' => 7,
+        'H' | 'Y' => 8,
+        'R' | 'Z' => 9,
+        digit => digit.to_digit(10).unwrap_or(0),
+    }
+}
+
+// United States, Canada and Mexico
+fn is_north_american(wmi: &str) -> bool {
+    matches!(wmi.chars().next(), Some('1'..='5'))
+}
+
+fn country(wmi: &str) -> Option<&'static str> {
+    let mut chars = wmi.chars();
+    let (first, second) = (chars.next()?, chars.next()?);
+    let order = |character: char| VIN_CHARACTERS.find(character);
+
+    COUNTRIES
+        .iter()
+        .find(|(region, from, to, _)| {
+            *region == first && (order(*from)..=order(*to)).contains(&order(second))
+        })
+        .map(|(_, _, _, country)| *country)
+}
+
+// Make and model of each WMI, embedded from data/wmi.csv. A newer file can be loaded on start
+// through VIN_CATALOG_FILE without rebuilding
+pub mod catalog {
+    use log::{error, info};
+    use serde::Deserialize;
+    use std::cmp::Reverse;
+    use std::collections::HashMap;
+    use std::env;
+    use std::sync::OnceLock;
+
+    const EMBEDDED_CATALOG: &str = include_str!("../data/wmi.csv");
+
+    static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+    // Rows without vds describe the WMI, the others the model of the VINs which VDS (characters
+    // 4 to 8) starts with vds
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct CatalogEntry {
+        pub wmi: String,
+        pub vds: String,
+        pub manufacturer: String,
+        pub make: String,
+        pub model: String,
+    }
+
+    #[derive(Debug, Default)]
+    pub struct Catalog {
+        manufacturers: HashMap<String, CatalogEntry>,
+        // Longest vds first so the most specific pattern wins
+        models: Vec<CatalogEntry>,
+    }
+
+    impl Catalog {
+        pub fn parse(data: &str) -> Result<Catalog, String> {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(data.as_bytes());
+
+            let mut catalog = Catalog::default();
+            for record in reader.deserialize() {
+                let mut entry: CatalogEntry =
+                    record.map_err(|err| format!("VIN catalog couldnt be parsed: {}", err))?;
+                entry.wmi = entry.wmi.to_ascii_uppercase();
+                entry.vds = entry.vds.to_ascii_uppercase();
+
+                if entry.wmi.len() != 3 {
+                    return Err(format!("WMI '{}' doesnt have 3 characters", entry.wmi));
+                }
+
+                if entry.vds.is_empty() {
+                    catalog.manufacturers.insert(entry.wmi.clone(), entry);
+                } else {
+                    catalog.models.push(entry);
+                }
+            }
+
+            catalog.models.sort_by_key(|entry| Reverse(entry.vds.len()));
+
+            Ok(catalog)
+        }
+
+        pub fn manufacturer(&self, wmi: &str) -> Option<&CatalogEntry> {
+            self.manufacturers.get(wmi)
+        }
+
+        pub fn model(&self, wmi: &str, vds: &str) -> Option<&str> {
+            self.models
+                .iter()
+                .find(|entry| entry.wmi == wmi && vds.starts_with(&entry.vds))
+                .map(|entry| entry.model.as_str())
+        }
+    }
+
+    // Loads the file on VIN_CATALOG_FILE if it's set, the embedded catalog otherwise
+    pub fn init_from_env() -> Result<(), String> {
+        let catalog = match env::var("VIN_CATALOG_FILE") {
+            Ok(path) => {
+                let data = std::fs::read_to_string(&path)
+                    .map_err(|err| format!("VIN catalog '{}' couldnt be read: {}", path, err))?;
+                info!("VIN catalog loaded from {}", path);
+                Catalog::parse(&data)?
+            }
+            Err(_) => embedded(),
+        };
+
+        CATALOG
+            .set(catalog)
+            .map_err(|_| String::from("VIN catalog was already loaded"))
+    }
+
+    // Catalog loaded on start, the embedded one if none was
+    pub fn catalog() -> &'static Catalog {
+        CATALOG.get_or_init(embedded)
+    }
+
+    fn embedded() -> Catalog {
+        Catalog::parse(EMBEDDED_CATALOG).unwrap_or_else(|err| {
+            error!("Embedded VIN catalog is invalid: {}", err);
+            Catalog::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::catalog::Catalog;
+    use super::{current_year, decode, decode_as_of, VinError};
+
+    #[test]
+    fn decodes_valid_vin() {
+        let information = decode(" 1hgcm82633a004352 ").unwrap();
+
+        assert_eq!(information.vin, "1HGCM82633A004352");
+        assert_eq!(information.wmi, "1HG");
+        assert_eq!(information.manufacturer.as_deref(), Some("Honda"));
+        assert_eq!(information.country.as_deref(), Some("United States"));
+        assert_eq!(information.model.as_deref(), Some("Accord"));
+        assert_eq!(information.years, vec!["2003"]);
+        assert_eq!(information.year.as_deref(), Some("2003"));
+        assert_eq!(information.plant, "A");
+        assert_eq!(information.serial, "004352");
+        assert!(information.check_digit_valid);
+    }
+
+    #[test]
+    fn rejects_malformed_vin() {
+        assert_eq!(decode("1HGCM82633A00435"), Err(VinError::Length(16)));
+        assert_eq!(decode(""), Err(VinError::Length(0)));
+
+        let res = decode("1HGCM82633O004352");
+        let expected = VinError::InvalidCharacter {
+            position: 11,
+            character: 'O',
+        };
+        assert_eq!(res, Err(expected));
+
+        let res = decode("1HGCM82633A00435Ñ");
+        let position = match res {
+            Err(VinError::InvalidCharacter { position, .. }) => position,
+            _ => 0,
+        };
+        assert_eq!(position, 17);
+    }
+
+    // Passes if the check digit is only enforced on North American VINs
+    #[test]
+    fn check_digit_required_in_north_america() {
+        let res = decode("1HGCM82643A004352");
+        let expected = VinError::CheckDigit {
+            expected: '3',
+            found: '4',
+        };
+        assert_eq!(res, Err(expected));
+
+        let information = decode("JHMCM82643A004352").unwrap();
+        assert!(!information.check_digit_valid);
+        assert_eq!(information.country.as_deref(), Some("Japan"));
+    }
+
+    #[test]
+    fn rejects_year_digit() {
+        let res = decode("JHMCM8264UA004352");
+        assert_eq!(res, Err(VinError::YearDigit('U')));
+    }
+
+    // Passes if the country is found on ranges ending in digits and unknown WMIs are kept
+    #[test]
+    fn unknown_manufacturer() {
+        let information = decode("8GGCM82643A004352").unwrap();
+
+        assert_eq!(information.country.as_deref(), Some("Chile"));
+        assert_eq!(information.manufacturer, None);
+
+        let information = decode("93ZCM82643A004352").unwrap();
+        assert_eq!(information.country.as_deref(), Some("Brazil"));
+    }
+
+    #[test]
+    fn infers_make_and_model() {
+        let information = decode("WVWZZZ1KZ6W000001").unwrap();
+        assert_eq!(information.make.as_deref(), Some("Volkswagen"));
+        assert_eq!(information.model.as_deref(), Some("Golf"));
+
+        // Known WMI without a pattern for its VDS
+        let information = decode("WVWZZZ9NZ6W000001").unwrap();
+        assert_eq!(information.make.as_deref(), Some("Volkswagen"));
+        assert_eq!(information.model, None);
+    }
+
+    // Passes if the longest matching VDS pattern is used
+    #[test]
+    fn catalog_patterns() {
+        let catalog = Catalog::parse(
+            "wmi,vds,manufacturer,make,model\n\
+             abc,,ACME Motors,Acme,\n\
+             ABC,1,,,Roadster\n\
+             ABC,12,,,Roadster GT\n",
+        )
+        .unwrap();
+
+        assert_eq!(catalog.manufacturer("ABC").unwrap().make, "Acme");
+        assert_eq!(catalog.model("ABC", "12345"), Some("Roadster GT"));
+        assert_eq!(catalog.model("ABC", "13345"), Some("Roadster"));
+        assert_eq!(catalog.model("ABD", "12345"), None);
+
+        assert!(Catalog::parse("wmi,vds,manufacturer,make,model\nAB,,A,A,\n").is_err());
+        assert!(Catalog::parse("wmi,vds\nABC,1\n").is_err());
+    }
+
+    // Passes if an alphabetic 7th character only keeps the 2010 cycle on North American VINs
+    #[test]
+    fn alphabetic_seventh_character() {
+        let information = decode_as_of("1HGCM8A68A0004352", 2040).unwrap();
+        assert_eq!(information.years, vec!["2010"]);
+        assert_eq!(information.year.as_deref(), Some("2010"));
+
+        let information = decode_as_of("JHMCM8A63A0004352", 2040).unwrap();
+        assert_eq!(information.years, vec!["1980", "2010"]);
+        assert_eq!(information.year, None);
+    }
+
+    // Passes if years after the next model year are dropped
+    #[test]
+    fn future_years_dropped() {
+        let information = decode_as_of("JHMCM8263TA004352", 2025).unwrap();
+        assert_eq!(information.years, vec!["1996", "2026"]);
+
+        let information = decode_as_of("JHMCM8263TA004352", 2024).unwrap();
+        assert_eq!(information.year.as_deref(), Some("1996"));
+
+        let res = decode_as_of("1HGCM8A65Y0004352", 2026);
+        assert_eq!(res.unwrap_err(), VinError::FutureYear('Y'));
+    }
+
+    #[test]
+    fn current_year_after_2025() {
+        assert!(current_year() >= 2025);
+    }
+}